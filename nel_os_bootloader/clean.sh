@@ -1,2 +1,2 @@
 #!/bin/sh -ex
-rm -rf iso fat.img nel_os.iso
+rm -rf iso fat.img nel_os.iso nel_os.sha256
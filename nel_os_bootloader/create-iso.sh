@@ -21,6 +21,18 @@ mcopy -i fat.img "$EFI_BINARY" ::/EFI/BOOT/BOOTX64.EFI
 mcopy -i fat.img ../nel_os_kernel/target/x86_64-nel_os/release/nel_os_kernel.elf ::/nel_os_kernel.elf
 mcopy -i fat.img bzImage ::/bzImage
 mcopy -i fat.img rootfs-n.cpio.gz ::/rootfs-n.cpio.gz
+mcopy -i fat.img nel_os.cfg ::/nel_os.cfg
+
+manifest_entry() {
+	echo "$(sha256sum "$1" | cut -d' ' -f1)  $2"
+}
+{
+	manifest_entry ../nel_os_kernel/target/x86_64-nel_os/release/nel_os_kernel.elf nel_os_kernel.elf
+	manifest_entry bzImage bzImage
+	manifest_entry rootfs-n.cpio.gz rootfs-n.cpio.gz
+	manifest_entry nel_os.cfg nel_os.cfg
+} > nel_os.sha256
+mcopy -i fat.img nel_os.sha256 ::/nel_os.sha256
 
 mkdir iso
 cp fat.img iso
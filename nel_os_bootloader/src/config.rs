@@ -0,0 +1,365 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use nel_os_common::module::ModuleKind;
+use uefi::{cstr16, println, CStr16};
+
+use crate::options::LoadOptions;
+
+pub const CONFIG_FILE: &CStr16 = cstr16!("nel_os.cfg");
+
+#[derive(Clone)]
+pub struct ModuleConfig {
+    pub kind: ModuleKind,
+    pub name: String,
+    /// Concatenated in order into a single module.
+    pub paths: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Profile {
+    pub name: String,
+    pub modules: Vec<ModuleConfig>,
+    pub cmdline: String,
+    pub guest_memory_size: u64,
+    /// `vm.<key> = <value>` entries, written to `/etc/nel_os/vm.conf` in the guest.
+    pub vm_conf: Vec<(String, String)>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            modules: vec![
+                ModuleConfig {
+                    kind: ModuleKind::Kernel,
+                    name: "bzImage".to_string(),
+                    paths: vec!["bzImage".to_string()],
+                },
+                ModuleConfig {
+                    kind: ModuleKind::Initrd,
+                    name: "initrd".to_string(),
+                    paths: vec!["rootfs-n.cpio.gz".to_string()],
+                },
+            ],
+            cmdline: "console=ttyS0 earlyprintk=serial nokaslr".to_string(),
+            guest_memory_size: 256 * 1024 * 1024,
+            vm_conf: Vec::new(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match key {
+            "bzimage" => self.set_module(ModuleKind::Kernel, "bzImage", value),
+            "initrd" => self.set_module(ModuleKind::Initrd, "initrd", value),
+            "module" => {
+                const USAGE: &str = "expected `module = <kind> <name> <path>...`";
+                // Fields may be separated by any run of whitespace.
+                let (kind, rest) = value.trim().split_once(char::is_whitespace).ok_or(USAGE)?;
+                let (name, paths) = rest
+                    .trim_start()
+                    .split_once(char::is_whitespace)
+                    .ok_or(USAGE)?;
+                let paths = paths.trim();
+                if paths.is_empty() {
+                    return Err(USAGE);
+                }
+                let kind = ModuleKind::from_name(kind).ok_or("unknown module kind")?;
+                self.set_module(kind, name, paths);
+            }
+            "cmdline" => self.cmdline = value.to_string(),
+            "memory" => {
+                let size = parse_size(value)?;
+                if size == 0 || size % 4096 != 0 {
+                    return Err("memory must be a non-zero multiple of 4KiB");
+                }
+                self.guest_memory_size = size;
+            }
+            _ => match key.strip_prefix("vm.") {
+                Some(name) => self.set_vm_conf(name, value)?,
+                None => return Err("unknown key"),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// `key += value` extends what a profile inherited instead of replacing it.
+    pub fn append(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match key {
+            "initrd" => match self.modules.iter_mut().find(|m| m.name == "initrd") {
+                Some(initrd) => initrd.paths.extend(split_paths(value)),
+                None => self.set_module(ModuleKind::Initrd, "initrd", value),
+            },
+            "cmdline" if self.cmdline.is_empty() => self.cmdline = value.to_string(),
+            "cmdline" => {
+                self.cmdline.push(' ');
+                self.cmdline.push_str(value);
+            }
+            _ => return Err("only initrd and cmdline can be appended to"),
+        }
+
+        Ok(())
+    }
+
+    fn set_vm_conf(&mut self, name: &str, value: &str) -> Result<(), &'static str> {
+        if name.is_empty() || name.contains(|c: char| c == '=' || c.is_whitespace()) {
+            return Err("invalid vm.conf key");
+        }
+
+        match self.vm_conf.iter_mut().find(|(key, _)| key == name) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.vm_conf.push((name.to_string(), value.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Lines for `/etc/nel_os/vm.conf`, in the order they were configured.
+    pub fn vm_conf(&self) -> String {
+        self.vm_conf
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect()
+    }
+
+    /// Replaces the module with the same name, so the file can override defaults.
+    fn set_module(&mut self, kind: ModuleKind, name: &str, paths: &str) {
+        let module = ModuleConfig {
+            kind,
+            name: name.to_string(),
+            paths: split_paths(paths).collect(),
+        };
+
+        match self.modules.iter_mut().find(|m| m.name == name) {
+            Some(existing) => *existing = module,
+            None => self.modules.push(module),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Keep whatever mode the firmware set up.
+    Current,
+    Largest,
+    /// Falls back to the largest mode when unavailable.
+    Exact(usize, usize),
+}
+
+impl Resolution {
+    fn parse(value: &str) -> Result<Self, &'static str> {
+        match value {
+            "current" => Ok(Self::Current),
+            "max" => Ok(Self::Largest),
+            _ => {
+                let (width, height) = value
+                    .split_once('x')
+                    .ok_or("expected `<width>x<height>`, `max` or `current`")?;
+                let width = width.trim().parse().map_err(|_| "invalid width")?;
+                let height = height.trim().parse().map_err(|_| "invalid height")?;
+                Ok(Self::Exact(width, height))
+            }
+        }
+    }
+}
+
+pub struct Config {
+    pub kernel: String,
+    pub resolution: Resolution,
+    /// Seconds before the default profile is booted. Zero skips the menu.
+    pub timeout: u64,
+    pub default: String,
+    /// `integrity = optional` boots without `nel_os.sha256`; by default its
+    /// absence stops the boot.
+    pub require_manifest: bool,
+    pub profiles: Vec<Profile>,
+    /// Settings given before the first `[profile]`; every profile starts from these.
+    base: Profile,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            kernel: "nel_os_kernel.elf".to_string(),
+            resolution: Resolution::Largest,
+            timeout: 5,
+            default: String::new(),
+            require_manifest: true,
+            profiles: Vec::new(),
+            base: Profile::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the contents of `CONFIG_FILE`, if it exists.
+    pub fn from_file(bytes: Option<&[u8]>) -> Self {
+        let mut config = Self::default();
+
+        match bytes {
+            Some(bytes) => match core::str::from_utf8(bytes) {
+                Ok(text) => config.parse(text),
+                Err(_) => println!("{}: not valid UTF-8, using defaults", CONFIG_FILE),
+            },
+            None => println!("{} not found, using defaults", CONFIG_FILE),
+        }
+
+        if config.profiles.is_empty() {
+            config.profiles.push(config.base.clone());
+        }
+
+        config
+    }
+
+    pub fn parse(&mut self, text: &str) {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let result = if let Some(section) = line.strip_prefix('[') {
+                match section.strip_suffix(']').map(str::trim) {
+                    Some(name) if !name.is_empty() => self.add_profile(name),
+                    _ => Err("expected `[profile name]`"),
+                }
+            } else {
+                match line.split_once('=') {
+                    Some((key, value)) => self.set(key.trim(), value.trim()),
+                    None => Err("expected `key = value`"),
+                }
+            };
+
+            if let Err(e) = result {
+                println!("{}:{}: {}", CONFIG_FILE, index + 1, e);
+            }
+        }
+    }
+
+    /// Index of the profile named by `default`, or the first one.
+    pub fn default_profile(&self) -> usize {
+        if self.default.is_empty() {
+            return 0;
+        }
+
+        self.profiles
+            .iter()
+            .position(|profile| profile.name == self.default)
+            .unwrap_or_else(|| {
+                println!("Default profile {} not found", self.default);
+                0
+            })
+    }
+
+    fn add_profile(&mut self, name: &str) -> Result<(), &'static str> {
+        if self.profiles.iter().any(|profile| profile.name == name) {
+            return Err("duplicate profile");
+        }
+
+        self.profiles.push(Profile {
+            name: name.to_string(),
+            ..self.base.clone()
+        });
+
+        Ok(())
+    }
+
+    /// Hypervisor options from LoadOptions take precedence over the file.
+    pub fn apply_options(&mut self, options: &LoadOptions) {
+        for (key, value) in options.pairs() {
+            let result = match key {
+                "profile" => {
+                    self.default = value.to_string();
+                    self.timeout = 0;
+                    Ok(())
+                }
+                "kernel" | "resolution" | "timeout" => self.set_global(key, value),
+                // Everything else is for the hypervisor itself.
+                _ => continue,
+            };
+
+            if let Err(e) = result {
+                println!("LoadOptions: {}={}: {}", key, value, e);
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match key {
+            "kernel" | "resolution" | "default" | "timeout" | "integrity"
+                if !self.profiles.is_empty() =>
+            {
+                Err("must be set before the first profile")
+            }
+            "kernel" | "resolution" | "default" | "timeout" | "integrity" => {
+                self.set_global(key, value)
+            }
+            _ => {
+                let profile = match self.profiles.last_mut() {
+                    Some(profile) => profile,
+                    None => &mut self.base,
+                };
+                match key.strip_suffix('+') {
+                    Some(key) => profile.append(key.trim_end(), value),
+                    None => profile.set(key, value),
+                }
+            }
+        }
+    }
+
+    fn set_global(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match key {
+            "kernel" => {
+                self.kernel = value.to_string();
+                Ok(())
+            }
+            "resolution" => {
+                self.resolution = Resolution::parse(value)?;
+                Ok(())
+            }
+            "default" => {
+                self.default = value.to_string();
+                Ok(())
+            }
+            "timeout" => {
+                self.timeout = value.parse().map_err(|_| "invalid timeout")?;
+                Ok(())
+            }
+            "integrity" => {
+                self.require_manifest = match value {
+                    "required" => true,
+                    "optional" => false,
+                    _ => return Err("expected `required` or `optional`"),
+                };
+                Ok(())
+            }
+            _ => Err("unknown key"),
+        }
+    }
+}
+
+fn split_paths(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split_whitespace().map(|path| path.to_string())
+}
+
+pub fn parse_size(value: &str) -> Result<u64, &'static str> {
+    let (digits, unit) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 1 << 10),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1 << 20),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    digits
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| "invalid size")?
+        .checked_mul(unit)
+        .ok_or("size is too large")
+}
@@ -0,0 +1,74 @@
+use alloc::string::String;
+use core::fmt;
+use uefi::Status;
+
+pub enum BootError {
+    /// The volume this loader was started from could not be opened.
+    FileSystem(Status),
+    InvalidPath(String),
+    NotFound(String),
+    Read {
+        path: String,
+        status: Status,
+    },
+    OutOfMemory {
+        what: String,
+        status: Status,
+    },
+    InvalidKernel(&'static str),
+    Integrity {
+        path: String,
+        reason: &'static str,
+    },
+    MemoryMap(Status),
+}
+
+impl BootError {
+    pub fn status(&self) -> Status {
+        match self {
+            BootError::FileSystem(status)
+            | BootError::Read { status, .. }
+            | BootError::OutOfMemory { status, .. }
+            | BootError::MemoryMap(status) => *status,
+            BootError::InvalidPath(_) => Status::INVALID_PARAMETER,
+            BootError::NotFound(_) => Status::NOT_FOUND,
+            BootError::InvalidKernel(_) => Status::LOAD_ERROR,
+            BootError::Integrity { .. } => Status::SECURITY_VIOLATION,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            BootError::InvalidPath(_) | BootError::NotFound(_) => {
+                Some("Check the file names in nel_os.cfg against the ESP")
+            }
+            BootError::InvalidKernel(_) => Some("Rebuild nel_os_kernel and copy it to the ESP"),
+            BootError::Integrity { .. } => {
+                Some("Regenerate nel_os.sha256 if the images were updated on purpose")
+            }
+            BootError::OutOfMemory { .. } => Some("Give the machine more memory"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootError::FileSystem(status) => write!(f, "cannot open the boot volume ({status:?})"),
+            BootError::InvalidPath(path) => write!(f, "{path}: not a valid UEFI path"),
+            BootError::NotFound(path) => write!(f, "{path}: file not found"),
+            BootError::Read { path, status } => write!(f, "{path}: read failed ({status:?})"),
+            BootError::OutOfMemory { what, status } => {
+                write!(f, "cannot allocate memory for {what} ({status:?})")
+            }
+            BootError::InvalidKernel(reason) => write!(f, "invalid kernel image: {reason}"),
+            BootError::Integrity { path, reason } => {
+                write!(f, "{path}: integrity check failed: {reason}")
+            }
+            BootError::MemoryMap(status) => {
+                write!(f, "cannot read the memory map ({status:?})")
+            }
+        }
+    }
+}
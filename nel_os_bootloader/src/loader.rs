@@ -0,0 +1,246 @@
+use alloc::{format, string::String, vec::Vec};
+use core::slice;
+use goblin::elf::{
+    self,
+    header::{EM_X86_64, ET_DYN, ET_EXEC},
+    program_header::{ProgramHeader, PT_LOAD},
+    reloc::{R_X86_64_NONE, R_X86_64_RELATIVE},
+    sym::STT_FUNC,
+};
+use nel_os_common::image::{KernelImage, KernelSegment, KernelSymbol, SegmentFlags};
+use uefi::boot::AllocateType;
+
+use crate::{error::BootError, LoaderPages};
+
+const PAGE_SIZE: u64 = 4096;
+/// Packed relative relocations; not in goblin's tag list.
+const DT_RELR: u64 = 36;
+
+/// Loads an `ET_EXEC` kernel at its link address, or an `ET_DYN` kernel at
+/// any suitably aligned base with its `R_X86_64_RELATIVE` relocations applied.
+pub fn load_kernel(bin: &[u8], loader_pages: &mut LoaderPages) -> Result<KernelImage, BootError> {
+    let elf = parse(bin)?;
+
+    let loads: Vec<&ProgramHeader> = elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .collect();
+    if loads.is_empty() {
+        return Err(BootError::InvalidKernel("no loadable segments"));
+    }
+
+    let mut link_start = u64::MAX;
+    let mut link_end = 0u64;
+    let mut align = PAGE_SIZE;
+    for header in &loads {
+        if header.p_filesz > header.p_memsz
+            || header
+                .p_offset
+                .checked_add(header.p_filesz)
+                .is_none_or(|end| end > bin.len() as u64)
+        {
+            return Err(BootError::InvalidKernel("segment lies outside the file"));
+        }
+
+        let end = header
+            .p_vaddr
+            .checked_add(header.p_memsz)
+            .ok_or(BootError::InvalidKernel("segment address overflows"))?;
+        link_start = link_start.min(header.p_vaddr);
+        link_end = link_end.max(end);
+        align = align.max(header.p_align);
+    }
+
+    if !align.is_power_of_two() {
+        return Err(BootError::InvalidKernel(
+            "segment alignment is not a power of two",
+        ));
+    }
+
+    let link_start = link_start & !(PAGE_SIZE - 1);
+    let link_end = link_end.next_multiple_of(PAGE_SIZE);
+    let pages = ((link_end - link_start) / PAGE_SIZE) as usize;
+
+    let start = match elf.header.e_type {
+        ET_DYN => {
+            // Over-allocate so the base can honour the largest p_align.
+            let slack = ((align - PAGE_SIZE) / PAGE_SIZE) as usize;
+            let base = allocate(
+                loader_pages,
+                AllocateType::AnyPages,
+                pages + slack,
+                link_start,
+                link_end,
+            )?;
+            // The first address at or above `base` congruent to `link_start`
+            // modulo `align`, so every p_vaddr keeps its offset within p_align.
+            base + (link_start.wrapping_sub(base) & (align - 1))
+        }
+        _ => allocate(
+            loader_pages,
+            AllocateType::Address(link_start),
+            pages,
+            link_start,
+            link_end,
+        )?,
+    };
+    let end = start + (link_end - link_start);
+    let load_bias = start.wrapping_sub(link_start);
+
+    unsafe { slice::from_raw_parts_mut(start as *mut u8, (end - start) as usize) }.fill(0);
+
+    let mut segments = Vec::with_capacity(loads.len());
+    for header in &loads {
+        let dest_start = header.p_vaddr.wrapping_add(load_bias);
+        let dest =
+            unsafe { slice::from_raw_parts_mut(dest_start as *mut u8, header.p_filesz as usize) };
+        let offset = header.p_offset as usize;
+        dest.copy_from_slice(&bin[offset..offset + header.p_filesz as usize]);
+
+        segments.push(KernelSegment {
+            start: dest_start,
+            end: dest_start + header.p_memsz,
+            flags: SegmentFlags::from_bits_truncate(header.p_flags),
+            _reserved: 0,
+        });
+    }
+
+    if elf.header.e_type == ET_DYN {
+        relocate(&elf, start, end, load_bias)?;
+    }
+
+    let (segments, segment_count, _) = segments.into_raw_parts();
+    let (symbols, names) = function_symbols(&elf, load_bias);
+    let (symbols, symbol_count, _) = symbols.into_raw_parts();
+    let (symbol_names, symbol_names_len, _) = names.into_bytes().into_raw_parts();
+
+    Ok(KernelImage {
+        start,
+        end,
+        load_bias,
+        entry: elf.entry.wrapping_add(load_bias),
+        segments,
+        segment_count: segment_count as u64,
+        symbols,
+        symbol_count: symbol_count as u64,
+        symbol_names,
+        symbol_names_len: symbol_names_len as u64,
+    })
+}
+
+/// Demangled function symbols from `.symtab`, sorted by loaded address, for
+/// the kernel's backtraces.
+fn function_symbols(elf: &elf::Elf<'_>, load_bias: u64) -> (Vec<KernelSymbol>, String) {
+    let mut functions: Vec<(u64, u64, &str)> = elf
+        .syms
+        .iter()
+        .filter(|sym| sym.st_type() == STT_FUNC && sym.st_value != 0)
+        .filter_map(|sym| {
+            let name = elf.strtab.get_at(sym.st_name)?;
+            Some((sym.st_value.wrapping_add(load_bias), sym.st_size, name))
+        })
+        .collect();
+    functions.sort_unstable_by_key(|&(addr, _, _)| addr);
+    functions.dedup_by_key(|&mut (addr, _, _)| addr);
+
+    let mut names = String::new();
+    let symbols = functions
+        .into_iter()
+        .map(|(addr, size, name)| {
+            let name_offset = names.len() as u32;
+            names.push_str(&format!("{:#}", rustc_demangle::demangle(name)));
+            KernelSymbol {
+                addr,
+                size,
+                name_offset,
+                name_len: names.len() as u32 - name_offset,
+            }
+        })
+        .collect();
+
+    (symbols, names)
+}
+
+fn parse(bin: &[u8]) -> Result<elf::Elf<'_>, BootError> {
+    if !bin.starts_with(b"\x7fELF") {
+        return Err(BootError::InvalidKernel("not an ELF file"));
+    }
+
+    let elf = elf::Elf::parse(bin).map_err(|_| BootError::InvalidKernel("malformed ELF file"))?;
+    if !elf.is_64 {
+        return Err(BootError::InvalidKernel("not an ELF64 image"));
+    }
+    if !elf.little_endian {
+        return Err(BootError::InvalidKernel("not a little-endian image"));
+    }
+    if elf.header.e_machine != EM_X86_64 {
+        return Err(BootError::InvalidKernel("not an x86_64 image"));
+    }
+    if !matches!(elf.header.e_type, ET_EXEC | ET_DYN) {
+        return Err(BootError::InvalidKernel(
+            "not an executable (ET_EXEC or ET_DYN)",
+        ));
+    }
+
+    Ok(elf)
+}
+
+fn allocate(
+    loader_pages: &mut LoaderPages,
+    ty: AllocateType,
+    pages: usize,
+    link_start: u64,
+    link_end: u64,
+) -> Result<u64, BootError> {
+    loader_pages
+        .allocate(ty, pages)
+        .map(|ptr| ptr.as_ptr() as u64)
+        .map_err(|e| BootError::OutOfMemory {
+            what: format!("the kernel at {link_start:#x}..{link_end:#x}"),
+            status: e.status(),
+        })
+}
+
+fn relocate(elf: &elf::Elf, start: u64, end: u64, load_bias: u64) -> Result<(), BootError> {
+    let has_relr = elf
+        .dynamic
+        .as_ref()
+        .is_some_and(|dynamic| dynamic.dyns.iter().any(|d| d.d_tag == DT_RELR));
+    if has_relr {
+        return Err(BootError::InvalidKernel(
+            "RELR relocations are not supported (link with -z nopack-relative-relocs)",
+        ));
+    }
+    if !elf.dynrels.is_empty() {
+        return Err(BootError::InvalidKernel(
+            "REL relocations are not supported",
+        ));
+    }
+
+    for reloc in elf.dynrelas.iter().chain(elf.pltrelocs.iter()) {
+        match reloc.r_type {
+            R_X86_64_NONE => {}
+            R_X86_64_RELATIVE => {
+                let target = reloc.r_offset.wrapping_add(load_bias);
+                if target < start || target.saturating_add(8) > end {
+                    return Err(BootError::InvalidKernel("relocation outside the image"));
+                }
+                let addend = reloc
+                    .r_addend
+                    .ok_or(BootError::InvalidKernel("relocation without an addend"))?;
+
+                unsafe {
+                    (target as *mut u64).write_unaligned(load_bias.wrapping_add_signed(addend));
+                }
+            }
+            _ => {
+                return Err(BootError::InvalidKernel(
+                    "unsupported relocation type (only R_X86_64_RELATIVE)",
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
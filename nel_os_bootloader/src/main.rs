@@ -4,10 +4,24 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, vec, vec::Vec};
-use core::{arch::asm, slice};
-use goblin::elf;
-use nel_os_common::{gop, memory};
+mod config;
+mod error;
+mod loader;
+mod menu;
+mod options;
+mod verify;
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{arch::asm, convert::Infallible, ptr::NonNull, slice};
+use nel_os_common::{
+    crash, gop, guest, measurement, memory, module, BootInfo, BootInfoFlags, BOOT_INFO_MAGIC,
+    BOOT_INFO_VERSION,
+};
 use uefi::{
     allocator::Allocator,
     boot::{AllocateType, MemoryType, ScopedProtocol},
@@ -15,15 +29,17 @@ use uefi::{
     prelude::*,
     println,
     proto::{
-        console::gop::{GraphicsOutput, PixelFormat},
+        console::gop::{GraphicsOutput, Mode, PixelFormat},
         media::{
-            file::{Directory, File, FileAttribute, FileInfo, FileMode},
+            file::{Directory, File, FileAttribute, FileInfo, FileMode, RegularFile},
             fs::SimpleFileSystem,
         },
     },
-    CStr16,
+    CStr16, CString16,
 };
 
+use crate::error::BootError;
+
 #[global_allocator]
 static ALLOCATOR: Allocator = Allocator;
 
@@ -35,103 +51,202 @@ fn hlt_loop() -> ! {
     }
 }
 
-fn get_fs() -> Directory {
+fn get_fs() -> Result<Directory, BootError> {
     let mut fs: ScopedProtocol<SimpleFileSystem> =
-        uefi::boot::get_image_file_system(uefi::boot::image_handle()).unwrap();
+        uefi::boot::get_image_file_system(uefi::boot::image_handle())
+            .map_err(|e| BootError::FileSystem(e.status()))?;
 
-    fs.open_volume().unwrap()
+    fs.open_volume()
+        .map_err(|e| BootError::FileSystem(e.status()))
 }
 
-fn read_file(name: &CStr16) -> Box<[u8]> {
-    let mut root = get_fs();
+fn open_file(name: &CStr16) -> Result<RegularFile, BootError> {
+    let mut root = get_fs()?;
     let file_info = root
         .open(name, FileMode::Read, FileAttribute::empty())
-        .unwrap();
-    let mut file = file_info.into_regular_file().unwrap();
+        .map_err(|e| match e.status() {
+            Status::NOT_FOUND => BootError::NotFound(name.to_string()),
+            status => BootError::Read {
+                path: name.to_string(),
+                status,
+            },
+        })?;
+
+    file_info.into_regular_file().ok_or(BootError::Read {
+        path: name.to_string(),
+        status: Status::INVALID_PARAMETER,
+    })
+}
 
-    let file_size = file.get_boxed_info::<FileInfo>().unwrap().file_size();
-    let mut buf = vec![0; file_size as usize];
+fn file_size(file: &mut RegularFile, name: &CStr16) -> Result<u64, BootError> {
+    file.get_boxed_info::<FileInfo>()
+        .map(|info| info.file_size())
+        .map_err(|e| BootError::Read {
+            path: name.to_string(),
+            status: e.status(),
+        })
+}
+
+fn read_into(file: &mut RegularFile, name: &CStr16, buf: &mut [u8]) -> Result<(), BootError> {
+    let read_size = file.read(buf).map_err(|e| BootError::Read {
+        path: name.to_string(),
+        status: e.status(),
+    })?;
+    if read_size != buf.len() {
+        return Err(BootError::Read {
+            path: name.to_string(),
+            status: Status::END_OF_FILE,
+        });
+    }
 
-    let read_size = file.read(&mut buf).unwrap();
     println!("file {} size: {}", name, read_size);
 
-    buf.into_boxed_slice()
+    Ok(())
 }
 
-fn load_file_to_laoder_data(name: &CStr16) -> (u64, u64) {
-    let mut root = get_fs();
-    let file_info = root
-        .open(name, FileMode::Read, FileAttribute::empty())
-        .expect("Failed to open file");
-    let mut file = file_info
-        .into_regular_file()
-        .expect("Failed to convert to regular file");
+fn uefi_path(path: &str) -> Result<CString16, BootError> {
+    CString16::try_from(path).map_err(|_| BootError::InvalidPath(path.to_string()))
+}
 
-    let file_size = file
-        .get_boxed_info::<FileInfo>()
-        .expect("Failed to get file info")
-        .file_size();
+fn read_file(name: &CStr16) -> Result<Box<[u8]>, BootError> {
+    let mut file = open_file(name)?;
 
-    let page_ptr = unsafe {
-        uefi::boot::allocate_pages(
-            AllocateType::AnyPages,
-            MemoryType::LOADER_DATA,
-            file_size.div_ceil(4096) as usize,
-        )
-        .expect("Failed to allocate pages")
-        .as_mut()
-    };
+    let file_size = file_size(&mut file, name)?;
+    let mut buf = vec![0; file_size as usize];
+    read_into(&mut file, name, &mut buf)?;
 
-    let buf = unsafe { slice::from_raw_parts_mut(page_ptr, file_size as usize) };
-    let read_size = file.read(buf).expect("Failed to read file");
-    println!("file {} size: {}", name, read_size);
+    Ok(buf.into_boxed_slice())
+}
 
-    (page_ptr as *mut u8 as u64, file_size)
+fn read_optional_file(name: &CStr16) -> Option<Box<[u8]>> {
+    match read_file(name) {
+        Ok(buf) => Some(buf),
+        Err(BootError::NotFound(_)) => None,
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
 }
 
-fn load_elf(bin: Box<[u8]>) -> u64 {
-    let elf = elf::Elf::parse(&bin).expect("Failed to parse elf");
-    let mut dest_start = u64::MAX;
-    let mut dest_end = 0u64;
+/// LOADER_DATA pages claimed by one boot attempt. A failed attempt frees them,
+/// so a retry finds the same memory free, fixed kernel addresses included.
+#[derive(Default)]
+pub struct LoaderPages(Vec<(NonNull<u8>, usize)>);
 
-    elf.program_headers
-        .iter()
-        .filter(|header| header.p_type == elf::program_header::PT_LOAD)
-        .for_each(|header| {
-            dest_start = dest_start.min(header.p_vaddr);
-            dest_end = dest_end.max(header.p_vaddr + header.p_memsz);
-        });
+impl LoaderPages {
+    pub fn allocate(&mut self, ty: AllocateType, pages: usize) -> uefi::Result<NonNull<u8>> {
+        let ptr = uefi::boot::allocate_pages(ty, MemoryType::LOADER_DATA, pages)?;
+        self.0.push((ptr, pages));
+        Ok(ptr)
+    }
 
-    uefi::boot::allocate_pages(
-        AllocateType::Address(dest_start),
-        MemoryType::LOADER_DATA,
-        dest_end
-            .checked_sub(dest_start)
-            .and_then(|size| size.checked_add(4095))
-            .map(|size| size / 4096)
-            .unwrap_or(0) as usize,
-    )
-    .expect("Failed to allocate pages");
-
-    elf.program_headers
-        .iter()
-        .filter(|header| header.p_type == elf::program_header::PT_LOAD)
-        .for_each(|header| {
-            let dest = unsafe {
-                slice::from_raw_parts_mut(header.p_vaddr as *mut u8, header.p_memsz as usize)
-            };
+    pub fn free_all(&mut self) {
+        for (ptr, pages) in self.0.drain(..) {
+            unsafe {
+                let _ = uefi::boot::free_pages(ptr, pages);
+            }
+        }
+    }
+}
 
-            let file_size = header.p_filesz as usize;
-            let offset = header.p_offset as usize;
+/// Loads `paths` back to back into one LOADER_DATA region. Every file starts on a
+/// 4-byte boundary with zero padding in between, as Linux expects for concatenated
+/// initramfs archives. `None` if none of the files exist; a set with only some of
+/// them missing is an error rather than a partial module.
+fn load_files_to_loader_data(
+    loader_pages: &mut LoaderPages,
+    paths: &[String],
+    measure: &mut impl FnMut(&str, &[u8]) -> Result<(), BootError>,
+) -> Result<Option<(u64, u64)>, BootError> {
+    let mut files = Vec::with_capacity(paths.len());
+    let mut missing = None;
+    let mut total_size = 0u64;
+    for path in paths {
+        let name = uefi_path(path)?;
+        let mut file = match open_file(&name) {
+            Ok(file) => file,
+            Err(BootError::NotFound(path)) => {
+                missing.get_or_insert(path);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let size = file_size(&mut file, &name)?;
+
+        let offset = total_size.next_multiple_of(4);
+        total_size = offset + size;
+        files.push((path, name, file, offset, size));
+    }
 
-            dest[..file_size].copy_from_slice(&bin[offset..offset + file_size]);
-            dest[file_size..].fill(0);
-        });
+    if files.is_empty() {
+        return Ok(None);
+    }
+    if let Some(path) = missing {
+        return Err(BootError::NotFound(path));
+    }
+
+    let pages = total_size.div_ceil(4096).max(1) as usize;
+    let page_ptr = loader_pages
+        .allocate(AllocateType::AnyPages, pages)
+        .map_err(|e| BootError::OutOfMemory {
+            what: paths.join(" "),
+            status: e.status(),
+        })?;
+
+    let buf = unsafe { slice::from_raw_parts_mut(page_ptr.as_ptr(), total_size as usize) };
+    buf.fill(0);
+
+    for (path, name, mut file, offset, size) in files {
+        let part = &mut buf[offset as usize..(offset + size) as usize];
+        read_into(&mut file, &name, part)?;
+        measure(path, part)?;
+    }
+
+    Ok(Some((page_ptr.as_ptr() as u64, total_size)))
+}
+
+fn select_mode(gop: &mut GraphicsOutput, resolution: config::Resolution) {
+    let modes: Vec<Mode> = gop
+        .modes()
+        .filter(|mode| mode.info().pixel_format() != PixelFormat::BltOnly)
+        .collect();
+
+    println!("GOP modes:");
+    for mode in &modes {
+        let (width, height) = mode.info().resolution();
+        println!("  {}x{} {:?}", width, height, mode.info().pixel_format());
+    }
 
-    elf.entry
+    let largest = || {
+        modes.iter().max_by_key(|mode| {
+            let (width, height) = mode.info().resolution();
+            width * height
+        })
+    };
+    let mode = match resolution {
+        config::Resolution::Current => return,
+        config::Resolution::Largest => largest(),
+        config::Resolution::Exact(width, height) => modes
+            .iter()
+            .find(|mode| mode.info().resolution() == (width, height))
+            .or_else(|| {
+                println!(
+                    "{}x{} is not available, using the largest mode",
+                    width, height
+                );
+                largest()
+            }),
+    };
+
+    if let Some(mode) = mode {
+        if let Err(e) = gop.set_mode(mode) {
+            println!("Failed to set GOP mode: {:?}", e.status());
+        }
+    }
 }
 
-fn get_frame_buffer() -> Option<gop::FrameBuffer> {
+fn get_frame_buffer(resolution: config::Resolution) -> Option<gop::FrameBuffer> {
     let gop_handle = if let Ok(gop_handle) = uefi::boot::get_handle_for_protocol::<GraphicsOutput>()
     {
         gop_handle
@@ -146,63 +261,295 @@ fn get_frame_buffer() -> Option<gop::FrameBuffer> {
         return None;
     };
 
+    select_mode(&mut gop, resolution);
+
     let info = gop.current_mode_info();
     let (width, height) = info.resolution();
     let frame_buffer = gop.frame_buffer().as_mut_ptr();
     let stride = info.stride();
     let pixel_format = info.pixel_format();
 
+    let (pixl_format, mask) = match (pixel_format, info.pixel_bitmask()) {
+        (PixelFormat::Rgb, _) => (gop::PixelFormat::Rgb, gop::PixelMask::default()),
+        (PixelFormat::Bgr, _) => (gop::PixelFormat::Bgr, gop::PixelMask::default()),
+        (PixelFormat::Bitmask, Some(mask)) => (
+            gop::PixelFormat::Bitmask,
+            gop::PixelMask {
+                red: mask.red,
+                green: mask.green,
+                blue: mask.blue,
+                reserved: mask.reserved,
+            },
+        ),
+        (format, _) => {
+            println!("Unsupported pixel format {:?}, no frame buffer", format);
+            return None;
+        }
+    };
+
+    println!("Frame buffer: {}x{} {:?}", width, height, pixel_format);
+
     Some(gop::FrameBuffer {
         frame_buffer,
         width,
         height,
         stride,
-        pixl_format: match pixel_format {
-            PixelFormat::Rgb => gop::PixelFormat::Rgb,
-            PixelFormat::Bgr => gop::PixelFormat::Bgr,
-            format => panic!("Unsupported pixel_format: {:?}", format),
-        },
+        pixl_format,
+        mask,
     })
 }
 
-fn get_rsdp() -> Option<u64> {
-    uefi::system::with_config_table(move |c| {
+fn find_config_table(guid: uefi::Guid) -> Option<u64> {
+    uefi::system::with_config_table(|c| {
         c.iter()
-            .find(|config| config.guid == uefi::table::cfg::ACPI_GUID)
+            .find(|config| config.guid == guid)
             .map(|config| config.address as u64)
-            .or(None)
     })
 }
 
+fn get_rsdp() -> Option<u64> {
+    find_config_table(uefi::table::cfg::ACPI2_GUID)
+        .or_else(|| find_config_table(uefi::table::cfg::ACPI_GUID))
+}
+
+/// Claims the crash log at the first free fixed address. The region is not
+/// cleared: it may hold the record of the previous boot. Runs before any other
+/// allocation, which could otherwise land on those addresses.
+fn allocate_crash_log() -> Option<u64> {
+    let pages = crash::CRASH_LOG_SIZE.div_ceil(4096) as usize;
+
+    crash::CRASH_LOG_ADDRESSES.into_iter().find(|&addr| {
+        uefi::boot::allocate_pages(
+            AllocateType::Address(addr),
+            MemoryType::custom(crash::CRASH_LOG_MEMORY_TYPE),
+            pages,
+        )
+        .is_ok()
+    })
+}
+
+fn memory_region_kind(ty: MemoryType) -> memory::MemoryRegionKind {
+    use memory::MemoryRegionKind;
+
+    if ty == MemoryType::custom(crash::CRASH_LOG_MEMORY_TYPE) {
+        return MemoryRegionKind::CrashLog;
+    }
+
+    match ty {
+        MemoryType::CONVENTIONAL
+        | MemoryType::BOOT_SERVICES_CODE
+        | MemoryType::BOOT_SERVICES_DATA => MemoryRegionKind::Usable,
+        MemoryType::LOADER_CODE | MemoryType::LOADER_DATA => MemoryRegionKind::Bootloader,
+        MemoryType::ACPI_RECLAIM => MemoryRegionKind::AcpiReclaim,
+        MemoryType::ACPI_NON_VOLATILE => MemoryRegionKind::AcpiNvs,
+        MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE => MemoryRegionKind::Mmio,
+        MemoryType::RUNTIME_SERVICES_CODE => MemoryRegionKind::RuntimeServicesCode,
+        MemoryType::RUNTIME_SERVICES_DATA => MemoryRegionKind::RuntimeServicesData,
+        MemoryType::UNUSABLE => MemoryRegionKind::Unusable,
+        MemoryType::PERSISTENT_MEMORY => MemoryRegionKind::Persistent,
+        _ => MemoryRegionKind::Reserved,
+    }
+}
+
 #[entry]
 fn main() -> Status {
-    uefi::helpers::init().unwrap();
+    // Nothing can be printed without the helpers; hand the error back to the firmware.
+    if let Err(e) = uefi::helpers::init() {
+        return e.status();
+    }
+
+    let crash_log = allocate_crash_log();
+
+    loop {
+        uefi::system::with_stdout(|stdout| {
+            let _ = stdout.clear();
+        });
+
+        println!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+        let mut loader_pages = LoaderPages::default();
+        let error = match boot(&mut loader_pages, crash_log) {
+            Ok(never) => match never {},
+            Err(e) => e,
+        };
+        loader_pages.free_all();
+
+        println!();
+        println!("Boot failed: {}", error);
+        if let Some(hint) = error.hint() {
+            println!("{}", hint);
+        }
+
+        if !menu::prompt_retry() {
+            return error.status();
+        }
+    }
+}
+
+fn boot(loader_pages: &mut LoaderPages, crash_log: Option<u64>) -> Result<Infallible, BootError> {
+    let options = options::LoadOptions::read();
+    options.print();
+
+    let config_file = read_optional_file(config::CONFIG_FILE);
+    let mut config = config::Config::from_file(config_file.as_deref());
+    config.apply_options(&options);
+
+    let manifest = verify::Manifest::load();
+    if manifest.is_none() {
+        if config.require_manifest {
+            return Err(BootError::Integrity {
+                path: verify::MANIFEST_FILE.to_string(),
+                reason: "manifest not found",
+            });
+        }
+        println!(
+            "{} not found and integrity = optional, skipping integrity checks",
+            verify::MANIFEST_FILE
+        );
+    }
+
+    let mut measurements: Vec<measurement::Measurement> = Vec::new();
+    let mut measure = |path: &str, data: &[u8]| -> Result<(), BootError> {
+        let digest = verify::sha256(data);
+        if let Some(manifest) = &manifest {
+            manifest
+                .verify(path, &digest)
+                .map_err(|reason| BootError::Integrity {
+                    path: path.to_string(),
+                    reason,
+                })?;
+        }
+        println!("sha256 {} {}", measurement::HexDigest(&digest), path);
+        measurements.push(measurement::Measurement::new(
+            path.to_string().leak(),
+            digest,
+        ));
+
+        Ok(())
+    };
 
-    uefi::system::with_stdout(|stdout| stdout.clear().unwrap());
+    // The configuration picks every path loaded below.
+    if let Some(bytes) = &config_file {
+        measure(&config::CONFIG_FILE.to_string(), bytes)?;
+    }
+
+    let selected = menu::select(&config.profiles, config.default_profile(), config.timeout);
+    let mut profile = config.profiles.swap_remove(selected);
+    if let Some(cmdline) = options.guest_cmdline.clone() {
+        profile.cmdline = cmdline;
+    }
+    if let Some(memory) = options.get("memory") {
+        if let Err(e) = profile.set("memory", memory) {
+            println!("LoadOptions: memory={}: {}", memory, e);
+        }
+    }
 
-    println!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    println!("Kernel: {}", config.kernel);
+    println!("Profile: {}", profile.name);
+    for module in &profile.modules {
+        println!(
+            "Module: {} ({}) <- {}",
+            module.name,
+            module.kind.name(),
+            module.paths.join(" ")
+        );
+    }
+    println!("Cmdline: {}", profile.cmdline);
+    let vm_conf = profile.vm_conf();
+    for line in vm_conf.lines() {
+        println!("vm.conf: {}", line);
+    }
+    println!(
+        "Guest memory: {}MiB",
+        profile.guest_memory_size / 1024 / 1024
+    );
+
+    let kernel = read_file(&uefi_path(&config.kernel)?)?;
+    measure(&config.kernel, &kernel)?;
+
+    let mut modules: Vec<module::Module> = Vec::with_capacity(profile.modules.len());
+    for module in profile.modules {
+        // Guest payloads are optional; the hypervisor can come up without them.
+        let Some((addr, size)) =
+            load_files_to_loader_data(loader_pages, &module.paths, &mut measure)?
+        else {
+            println!("Module {}: no files found, skipping", module.name);
+            continue;
+        };
+        modules.push(module::Module::new(
+            module.name.leak(),
+            addr,
+            size,
+            module.kind,
+        ));
+    }
 
-    let kernel = read_file(cstr16!("nel_os_kernel.elf"));
+    if manifest.is_some() {
+        println!("All images match {}", verify::MANIFEST_FILE);
+    }
 
-    let (bzimage_addr, bzimage_size) = load_file_to_laoder_data(cstr16!("bzImage"));
-    let (rootfs_addr, rootfs_size) = load_file_to_laoder_data(cstr16!("rootfs-n.cpio.gz"));
+    let has_guest = modules
+        .iter()
+        .any(|module| module.kind() == module::ModuleKind::Kernel);
+    if !has_guest {
+        println!("No guest kernel, the hypervisor will start without a guest");
+    }
 
-    let entry_point = load_elf(kernel);
+    let kernel_image = loader::load_kernel(&kernel, loader_pages)?;
+    drop(kernel);
+
+    println!(
+        "Kernel image: {:#x}..{:#x} (bias {:#x})",
+        kernel_image.start, kernel_image.end, kernel_image.load_bias
+    );
+    for segment in kernel_image.segments() {
+        println!(
+            "  {:#x}..{:#x} {}",
+            segment.start, segment.end, segment.flags
+        );
+    }
+    println!("Entry point: {:#x}", kernel_image.entry);
+    println!("Kernel symbols: {}", kernel_image.symbol_count);
 
-    println!("Entry point: {:#x}", entry_point);
+    let entry: extern "sysv64" fn(&BootInfo) = unsafe { core::mem::transmute(kernel_image.entry) };
 
-    let entry: extern "sysv64" fn(&nel_os_common::BootInfo) =
-        unsafe { core::mem::transmute(entry_point) };
+    let frame_buffer = get_frame_buffer(config.resolution);
 
-    let frame_buffer = get_frame_buffer();
+    match crash_log {
+        Some(addr) => println!("Crash log: {:#x}", addr),
+        None => println!("Crash log: no fixed address available"),
+    }
 
     let rsdp = get_rsdp();
+    let smbios = find_config_table(uefi::table::cfg::SMBIOS_GUID);
+    let smbios3 = find_config_table(uefi::table::cfg::SMBIOS3_GUID);
+    let system_table = uefi::table::system_table_raw().map(|table| table.as_ptr() as u64);
+    println!(
+        "Firmware: {} rev {:#x}",
+        uefi::system::firmware_vendor(),
+        uefi::system::firmware_revision()
+    );
+
+    let hypervisor_cmdline = options.hypervisor.leak();
+    let vm_conf = vm_conf.leak();
+    let profile_name = profile.name.leak();
+    let cmdline = profile.cmdline.leak();
+    let guest = guest::GuestConfig {
+        profile: profile_name.as_ptr(),
+        profile_len: profile_name.len() as u64,
+        cmdline: cmdline.as_ptr(),
+        cmdline_len: cmdline.len() as u64,
+        vm_conf: vm_conf.as_ptr(),
+        vm_conf_len: vm_conf.len() as u64,
+        memory_size: profile.guest_memory_size,
+    };
 
     let size = uefi::boot::memory_map(MemoryType::LOADER_DATA)
-        .unwrap()
+        .map_err(|e| BootError::MemoryMap(e.status()))?
         .len()
         + 8 * core::mem::size_of::<memory::Range>();
     let mut ranges: Vec<memory::Range> = Vec::with_capacity(size);
+    let mut regions: Vec<memory::MemoryRegion> = Vec::with_capacity(size);
 
     println!("Usable memory table size: {}", size);
 
@@ -225,6 +572,17 @@ fn main() -> Status {
             })
         });
 
+    for entry in memory_map.entries() {
+        let start = entry.phys_start;
+        let end = start + entry.page_count * 4096;
+        let kind = memory_region_kind(entry.ty);
+
+        match regions.last_mut() {
+            Some(last) if last.end == start && last.kind() == kind => last.end = end,
+            _ => regions.push(memory::MemoryRegion::new(start, end, kind)),
+        }
+    }
+
     let usable_memory = {
         let (ptr, len, _) = ranges.into_raw_parts();
         memory::UsableMemory {
@@ -233,14 +591,79 @@ fn main() -> Status {
         }
     };
 
-    entry(&nel_os_common::BootInfo {
+    let memory_map = {
+        let (ptr, len, _) = regions.into_raw_parts();
+        memory::MemoryMap {
+            regions: ptr as *const memory::MemoryRegion,
+            len: len as u64,
+        }
+    };
+
+    let modules = {
+        let (ptr, len, _) = modules.into_raw_parts();
+        module::ModuleList {
+            modules: ptr as *const module::Module,
+            len: len as u64,
+        }
+    };
+
+    let measurements = {
+        let (ptr, len, _) = measurements.into_raw_parts();
+        measurement::MeasurementList {
+            measurements: ptr as *const measurement::Measurement,
+            len: len as u64,
+        }
+    };
+
+    let mut flags = BootInfoFlags::CMDLINE
+        | BootInfoFlags::MEMORY_MAP
+        | BootInfoFlags::MODULES
+        | BootInfoFlags::MEASUREMENTS
+        | BootInfoFlags::KERNEL_IMAGE;
+    if has_guest {
+        flags.insert(BootInfoFlags::GUEST);
+    }
+    if manifest.is_some() {
+        flags.insert(BootInfoFlags::VERIFIED);
+    }
+    if frame_buffer.is_some() {
+        flags.insert(BootInfoFlags::FRAME_BUFFER);
+    }
+    if rsdp.is_some() {
+        flags.insert(BootInfoFlags::RSDP);
+    }
+    if smbios.is_some() {
+        flags.insert(BootInfoFlags::SMBIOS);
+    }
+    if smbios3.is_some() {
+        flags.insert(BootInfoFlags::SMBIOS3);
+    }
+    if system_table.is_some() {
+        flags.insert(BootInfoFlags::EFI_SYSTEM_TABLE);
+    }
+    if crash_log.is_some() {
+        flags.insert(BootInfoFlags::CRASH_LOG);
+    }
+
+    entry(&BootInfo {
+        magic: BOOT_INFO_MAGIC,
+        version: BOOT_INFO_VERSION,
+        size: BootInfo::SIZE,
+        flags,
+        cmdline: hypervisor_cmdline.as_ptr(),
+        cmdline_len: hypervisor_cmdline.len() as u64,
         usable_memory,
-        frame_buffer,
-        rsdp,
-        bzimage_addr,
-        bzimage_size,
-        rootfs_addr,
-        rootfs_size,
+        memory_map,
+        frame_buffer: frame_buffer.unwrap_or(gop::FrameBuffer::empty()),
+        rsdp: rsdp.unwrap_or(0),
+        smbios: smbios.unwrap_or(0),
+        smbios3: smbios3.unwrap_or(0),
+        efi_system_table: system_table.unwrap_or(0),
+        crash_log: crash_log.unwrap_or(0),
+        modules,
+        guest,
+        measurements,
+        kernel_image,
     });
 
     hlt_loop();
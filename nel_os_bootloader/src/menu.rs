@@ -0,0 +1,114 @@
+use uefi::{
+    boot::{self, EventType, TimerTrigger, Tpl},
+    println,
+    proto::console::text::{Key, ScanCode},
+    Char16,
+};
+
+use crate::config::Profile;
+
+/// 100ns units, as expected by `set_timer`.
+const ONE_SECOND: u64 = 10_000_000;
+// Safe: carriage return is a valid UCS-2 character.
+const ENTER: Char16 = unsafe { Char16::from_u16_unchecked(b'\r' as u16) };
+
+/// Lets the user pick a profile. The default boots after `timeout` seconds
+/// unless a key is pressed first, after which the menu waits for Enter.
+pub fn select(profiles: &[Profile], default: usize, timeout: u64) -> usize {
+    if profiles.len() <= 1 || timeout == 0 {
+        return default;
+    }
+
+    let Some(key_event) = uefi::system::with_stdin(|stdin| stdin.wait_for_key_event()) else {
+        println!("No console input, booting the default profile");
+        return default;
+    };
+
+    let timer = match unsafe { boot::create_event(EventType::TIMER, Tpl::APPLICATION, None, None) }
+    {
+        Ok(timer) => timer,
+        Err(e) => {
+            println!("Failed to create the menu timer: {:?}", e);
+            return default;
+        }
+    };
+    if boot::set_timer(&timer, TimerTrigger::Periodic(ONE_SECOND)).is_err() {
+        let _ = boot::close_event(timer);
+        return default;
+    }
+
+    let mut selected = default;
+    let mut remaining = Some(timeout);
+    loop {
+        draw(profiles, selected, remaining);
+
+        let mut events = unsafe { [key_event.unsafe_clone(), timer.unsafe_clone()] };
+        match boot::wait_for_event(&mut events) {
+            Ok(0) => {
+                remaining = None;
+                match uefi::system::with_stdin(|stdin| stdin.read_key()) {
+                    Ok(Some(Key::Special(ScanCode::UP))) => {
+                        selected = selected.checked_sub(1).unwrap_or(profiles.len() - 1);
+                    }
+                    Ok(Some(Key::Special(ScanCode::DOWN))) => {
+                        selected = (selected + 1) % profiles.len();
+                    }
+                    Ok(Some(Key::Printable(c))) if c == ENTER => break,
+                    _ => {}
+                }
+            }
+            Ok(_) => match remaining {
+                Some(0 | 1) => break,
+                Some(seconds) => remaining = Some(seconds - 1),
+                None => {}
+            },
+            Err(_) => break,
+        }
+    }
+
+    let _ = boot::close_event(timer);
+
+    selected
+}
+
+fn draw(profiles: &[Profile], selected: usize, remaining: Option<u64>) {
+    // A console that cannot clear just scrolls; the menu is still usable.
+    uefi::system::with_stdout(|stdout| {
+        let _ = stdout.clear();
+    });
+
+    println!("Select a guest profile (Up/Down, Enter to boot):");
+    println!();
+    for (index, profile) in profiles.iter().enumerate() {
+        let marker = if index == selected { '>' } else { ' ' };
+        println!("{} {}", marker, profile.name);
+    }
+    println!();
+
+    match remaining {
+        Some(seconds) => println!("Booting {} in {}s", profiles[selected].name, seconds),
+        None => println!("Booting {} on Enter", profiles[selected].name),
+    }
+}
+
+/// Asks whether to retry after a failed boot. Returns false to give control back to the firmware.
+pub fn prompt_retry() -> bool {
+    let Some(key_event) = uefi::system::with_stdin(|stdin| stdin.wait_for_key_event()) else {
+        return false;
+    };
+
+    println!("Press Enter to retry (and pick another profile), Esc to exit");
+
+    loop {
+        let mut events = unsafe { [key_event.unsafe_clone()] };
+        if boot::wait_for_event(&mut events).is_err() {
+            return false;
+        }
+
+        match uefi::system::with_stdin(|stdin| stdin.read_key()) {
+            Ok(Some(Key::Printable(c))) if c == ENTER => return true,
+            Ok(Some(Key::Special(ScanCode::ESCAPE))) => return false,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use uefi::{boot, println, proto::loaded_image::LoadedImage};
+
+/// Arguments from the UEFI shell or boot entry, e.g.
+/// `profile=debug memory=512M -- console=ttyS0 init=/bin/sh`.
+/// Everything before `--` is for the hypervisor, everything after is the guest cmdline.
+#[derive(Default)]
+pub struct LoadOptions {
+    pub hypervisor: String,
+    pub guest_cmdline: Option<String>,
+}
+
+impl LoadOptions {
+    pub fn read() -> Self {
+        let Ok(image) = boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()) else {
+            return Self::default();
+        };
+
+        match image.load_options_as_cstr16() {
+            Ok(options) => Self::parse(&options.to_string()),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn parse(text: &str) -> Self {
+        let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+        // The UEFI shell passes the image path as the first argument.
+        let text = match text.split_once(char::is_whitespace) {
+            Some((first, rest)) if is_image_path(first) => rest.trim_start(),
+            None if is_image_path(text) => "",
+            _ => text,
+        };
+
+        // Only a standalone `--` separates; guest arguments may contain dashes themselves.
+        let padded = format!(" {text} ");
+        let (hypervisor, guest_cmdline) = match padded.split_once(" -- ") {
+            Some((hypervisor, guest)) => (hypervisor, Some(guest.trim().to_string())),
+            None => (padded.as_str(), None),
+        };
+
+        Self {
+            hypervisor: hypervisor.trim().to_string(),
+            guest_cmdline,
+        }
+    }
+
+    /// `key=value` pairs from the hypervisor section; bare words are skipped.
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.hypervisor
+            .split_whitespace()
+            .filter_map(|option| option.split_once('='))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+            .last()
+    }
+
+    pub fn print(&self) {
+        if !self.hypervisor.is_empty() {
+            println!("Hypervisor options: {}", self.hypervisor);
+        }
+        if let Some(cmdline) = &self.guest_cmdline {
+            println!("Guest cmdline from LoadOptions: {}", cmdline);
+        }
+    }
+}
+
+fn is_image_path(arg: &str) -> bool {
+    arg.len() >= 4
+        && arg
+            .get(arg.len() - 4..)
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(".efi"))
+}
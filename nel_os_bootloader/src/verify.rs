@@ -0,0 +1,94 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use nel_os_common::measurement::HexDigest;
+use sha2::{Digest, Sha256};
+use uefi::{cstr16, println, CStr16};
+
+/// `sha256sum` output format: `<hex digest>  <path>` per line.
+pub const MANIFEST_FILE: &CStr16 = cstr16!("nel_os.sha256");
+
+pub struct Manifest {
+    entries: Vec<(String, [u8; 32])>,
+}
+
+impl Manifest {
+    pub fn load() -> Option<Self> {
+        let bytes = crate::read_optional_file(MANIFEST_FILE)?;
+        let Ok(text) = core::str::from_utf8(&bytes) else {
+            println!("{}: not valid UTF-8", MANIFEST_FILE);
+            return Some(Self {
+                entries: Vec::new(),
+            });
+        };
+
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match parse_line(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => println!("{}:{}: {}", MANIFEST_FILE, index + 1, e),
+            }
+        }
+
+        Some(Self { entries })
+    }
+
+    /// Files missing from the manifest fail too, so a stale manifest can't be bypassed.
+    pub fn verify(&self, path: &str, digest: &[u8; 32]) -> Result<(), &'static str> {
+        let path = normalize(path);
+        let (_, expected) = self
+            .entries
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(path))
+            .ok_or("not listed in the manifest")?;
+
+        if expected != digest {
+            println!("  expected {}", HexDigest(expected));
+            println!("  measured {}", HexDigest(digest));
+            return Err("SHA-256 mismatch");
+        }
+
+        Ok(())
+    }
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn parse_line(line: &str) -> Result<(String, [u8; 32]), &'static str> {
+    let (hex, path) = line
+        .split_once(char::is_whitespace)
+        .ok_or("expected `<sha256> <path>`")?;
+    // sha256sum marks binary-mode entries with a leading `*`.
+    let path = path.trim_start().trim_start_matches('*');
+    if path.is_empty() {
+        return Err("expected `<sha256> <path>`");
+    }
+
+    Ok((normalize(path).to_string(), parse_digest(hex)?))
+}
+
+fn parse_digest(hex: &str) -> Result<[u8; 32], &'static str> {
+    if hex.len() != 64 {
+        return Err("digest must be 64 hex digits");
+    }
+
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = core::str::from_utf8(pair).map_err(|_| "invalid digest")?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| "invalid digest")?;
+    }
+
+    Ok(digest)
+}
+
+fn normalize(path: &str) -> &str {
+    path.trim_start_matches(['/', '\\'])
+}
@@ -0,0 +1,144 @@
+use core::mem;
+
+pub const CRASH_LOG_MAGIC: u64 = u64::from_le_bytes(*b"NELCRASH");
+/// Bump whenever the layout of `CrashRecord` changes.
+pub const CRASH_LOG_VERSION: u32 = 1;
+pub const CRASH_LOG_SIZE: u64 = 0x4000;
+/// Fixed physical addresses tried in order, so the next boot looks where the
+/// last one wrote as long as the firmware leaves that memory free.
+pub const CRASH_LOG_ADDRESSES: [u64; 3] = [0x0400_0000, 0x0800_0000, 0x1000_0000];
+/// OS-defined UEFI memory type the region is allocated as.
+pub const CRASH_LOG_MEMORY_TYPE: u32 = 0x8000_4e45;
+
+/// `CrashRecord::vector` of a panic that was not caused by an exception.
+pub const CRASH_VECTOR_PANIC: u32 = u32::MAX;
+
+pub const CRASH_BACKTRACE_LEN: usize = 16;
+pub const CRASH_VM_EXITS_LEN: usize = 16;
+pub const CRASH_MESSAGE_LEN: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrashRegisters {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub cs: u64,
+    pub ss: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrashVmExit {
+    pub reason: u32,
+    pub _reserved: u32,
+    pub qualification: u64,
+    pub guest_rip: u64,
+    pub uptime_ns: u64,
+}
+
+/// What the kernel leaves in the crash log region when it dies. Only valid if
+/// `magic`, `version` and `checksum` all match.
+#[repr(C)]
+pub struct CrashRecord {
+    pub magic: u64,
+    pub version: u32,
+    /// FNV-1a over the record with this field zeroed.
+    pub checksum: u32,
+    pub uptime_ns: u64,
+    pub cpu: u32,
+    /// Exception vector, or `CRASH_VECTOR_PANIC`.
+    pub vector: u32,
+    pub error_code: u64,
+    /// Non-zero if `registers` holds the state at the exception.
+    pub has_registers: u32,
+    pub message_len: u32,
+    pub registers: CrashRegisters,
+    pub backtrace_len: u32,
+    pub vm_exit_count: u32,
+    /// Call sites, innermost first.
+    pub backtrace: [u64; CRASH_BACKTRACE_LEN],
+    /// Oldest first.
+    pub vm_exits: [CrashVmExit; CRASH_VM_EXITS_LEN],
+    pub message: [u8; CRASH_MESSAGE_LEN],
+}
+
+const _: () = assert!(mem::size_of::<CrashRecord>() as u64 <= CRASH_LOG_SIZE);
+
+impl CrashRecord {
+    pub fn is_valid(&self) -> bool {
+        self.magic == CRASH_LOG_MAGIC
+            && self.version == CRASH_LOG_VERSION
+            && self.checksum == self.compute_checksum()
+    }
+
+    /// Marks the record complete; call after every other field is written.
+    pub fn seal(&mut self) {
+        self.magic = CRASH_LOG_MAGIC;
+        self.version = CRASH_LOG_VERSION;
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Zeroes everything, so a stale or half-written record is never reported.
+    pub fn clear(&mut self) {
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(self as *mut Self as *mut u8, mem::size_of::<Self>())
+        };
+        bytes.fill(0);
+    }
+
+    pub fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(CRASH_MESSAGE_LEN);
+        match core::str::from_utf8(&self.message[..len]) {
+            Ok(message) => message,
+            // Truncation can split a character.
+            Err(e) => core::str::from_utf8(&self.message[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+
+    pub fn backtrace(&self) -> &[u64] {
+        &self.backtrace[..(self.backtrace_len as usize).min(CRASH_BACKTRACE_LEN)]
+    }
+
+    pub fn vm_exits(&self) -> &[CrashVmExit] {
+        &self.vm_exits[..(self.vm_exit_count as usize).min(CRASH_VM_EXITS_LEN)]
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, mem::size_of::<Self>())
+        };
+        let checksum_at = mem::offset_of!(CrashRecord, checksum);
+
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (checksum_at..checksum_at + 4).contains(&i) {
+                    0
+                } else {
+                    b
+                }
+            })
+            .fold(0x811c_9dc5u32, |hash, b| {
+                (hash ^ b as u32).wrapping_mul(0x0100_0193)
+            })
+    }
+}
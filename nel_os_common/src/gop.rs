@@ -1,12 +1,46 @@
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PixelFormat {
-    Rgb,
-    Bgr,
+    Rgb = 0,
+    Bgr = 1,
+    /// Channel layout is given by `FrameBuffer::mask`.
+    Bitmask = 2,
 }
 
+/// Bits of each channel within a 32-bit pixel. Only meaningful for `PixelFormat::Bitmask`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PixelMask {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
 pub struct FrameBuffer {
     pub frame_buffer: *mut u8,
     pub width: usize,
     pub height: usize,
     pub stride: usize,
     pub pixl_format: PixelFormat,
+    pub mask: PixelMask,
+}
+
+impl FrameBuffer {
+    pub const fn empty() -> Self {
+        Self {
+            frame_buffer: core::ptr::null_mut(),
+            width: 0,
+            height: 0,
+            stride: 0,
+            pixl_format: PixelFormat::Rgb,
+            mask: PixelMask {
+                red: 0,
+                green: 0,
+                blue: 0,
+                reserved: 0,
+            },
+        }
+    }
 }
@@ -0,0 +1,25 @@
+#[repr(C)]
+pub struct GuestConfig {
+    pub profile: *const u8,
+    pub profile_len: u64,
+    pub cmdline: *const u8,
+    pub cmdline_len: u64,
+    /// `key=value` lines for `/etc/nel_os/vm.conf` in the guest.
+    pub vm_conf: *const u8,
+    pub vm_conf_len: u64,
+    pub memory_size: u64,
+}
+
+impl GuestConfig {
+    pub fn profile(&self) -> &str {
+        crate::str_from_raw(self.profile, self.profile_len)
+    }
+
+    pub fn cmdline(&self) -> &str {
+        crate::str_from_raw(self.cmdline, self.cmdline_len)
+    }
+
+    pub fn vm_conf(&self) -> &str {
+        crate::str_from_raw(self.vm_conf, self.vm_conf_len)
+    }
+}
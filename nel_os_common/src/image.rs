@@ -0,0 +1,126 @@
+use core::fmt;
+
+/// Same bit layout as the ELF `p_flags` field.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SegmentFlags(u32);
+
+impl SegmentFlags {
+    pub const EXECUTE: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const READ: Self = Self(1 << 2);
+
+    pub const fn from_bits_truncate(bits: u32) -> Self {
+        Self(bits & 0b111)
+    }
+
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl fmt::Display for SegmentFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |flag, c| if self.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(Self::READ, 'r'),
+            flag(Self::WRITE, 'w'),
+            flag(Self::EXECUTE, 'x')
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelSegment {
+    pub start: u64,
+    pub end: u64,
+    pub flags: SegmentFlags,
+    pub _reserved: u32,
+}
+
+/// A function in the kernel image, at its loaded address.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelSymbol {
+    pub addr: u64,
+    pub size: u64,
+    /// Byte range of the demangled name in `KernelImage::symbol_names`.
+    pub name_offset: u32,
+    pub name_len: u32,
+}
+
+#[repr(C)]
+pub struct KernelImage {
+    /// Page-aligned range of everything the bootloader allocated for the image.
+    pub start: u64,
+    pub end: u64,
+    /// Added to every link-time address; zero for `ET_EXEC` kernels.
+    pub load_bias: u64,
+    pub entry: u64,
+    pub segments: *const KernelSegment,
+    pub segment_count: u64,
+    /// Sorted by `addr`; empty if the ELF had no symbol table.
+    pub symbols: *const KernelSymbol,
+    pub symbol_count: u64,
+    pub symbol_names: *const u8,
+    pub symbol_names_len: u64,
+}
+
+impl KernelImage {
+    pub fn segments(&self) -> &[KernelSegment] {
+        if self.segments.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.segments, self.segment_count as usize) }
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+
+    pub fn symbols(&self) -> &[KernelSymbol] {
+        if self.symbols.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.symbols, self.symbol_count as usize) }
+    }
+
+    pub fn symbol_name(&self, symbol: &KernelSymbol) -> &str {
+        if self.symbol_names.is_null() {
+            return "";
+        }
+
+        let names = unsafe {
+            core::slice::from_raw_parts(self.symbol_names, self.symbol_names_len as usize)
+        };
+        let start = symbol.name_offset as usize;
+        names
+            .get(start..start + symbol.name_len as usize)
+            .and_then(|name| core::str::from_utf8(name).ok())
+            .unwrap_or("")
+    }
+
+    /// The function containing `addr` and the offset into it.
+    pub fn symbolize(&self, addr: u64) -> Option<(&str, u64)> {
+        let symbols = self.symbols();
+        let index = symbols
+            .partition_point(|symbol| symbol.addr <= addr)
+            .checked_sub(1)?;
+        let symbol = &symbols[index];
+        let offset = addr - symbol.addr;
+        if offset >= symbol.size.max(1) {
+            return None;
+        }
+
+        Some((self.symbol_name(symbol), offset))
+    }
+}
@@ -1,16 +1,222 @@
 #![no_std]
 
-use crate::{gop::FrameBuffer, memory::UsableMemory};
+use core::{fmt, ops::BitOr};
 
+use crate::{
+    gop::FrameBuffer,
+    guest::GuestConfig,
+    image::KernelImage,
+    measurement::{Measurement, MeasurementList},
+    memory::{MemoryMap, MemoryRegion, UsableMemory},
+    module::{Module, ModuleList},
+};
+
+pub mod crash;
 pub mod gop;
+pub mod guest;
+pub mod image;
+pub mod measurement;
 pub mod memory;
+pub mod module;
+
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"NELOSBI\0");
+/// Bump whenever the layout of `BootInfo` or anything it points to changes.
+pub const BOOT_INFO_VERSION: u32 = 11;
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootInfoFlags(u64);
+
+impl BootInfoFlags {
+    pub const MEMORY_MAP: Self = Self(1 << 0);
+    pub const FRAME_BUFFER: Self = Self(1 << 1);
+    pub const RSDP: Self = Self(1 << 2);
+    pub const MODULES: Self = Self(1 << 3);
+    pub const GUEST: Self = Self(1 << 4);
+    pub const MEASUREMENTS: Self = Self(1 << 5);
+    /// Every measurement matched the manifest on the ESP.
+    pub const VERIFIED: Self = Self(1 << 6);
+    pub const KERNEL_IMAGE: Self = Self(1 << 7);
+    pub const SMBIOS: Self = Self(1 << 8);
+    pub const SMBIOS3: Self = Self(1 << 9);
+    pub const EFI_SYSTEM_TABLE: Self = Self(1 << 10);
+    pub const CMDLINE: Self = Self(1 << 11);
+    pub const CRASH_LOG: Self = Self(1 << 12);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
 
+impl BitOr for BootInfoFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootInfoError {
+    BadMagic(u64),
+    VersionMismatch { expected: u32, found: u32 },
+    SizeMismatch { expected: u32, found: u32 },
+}
+
+impl fmt::Display for BootInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootInfoError::BadMagic(magic) => write!(f, "bad magic {magic:#x}"),
+            BootInfoError::VersionMismatch { expected, found } => {
+                write!(f, "version {found} (expected {expected})")
+            }
+            BootInfoError::SizeMismatch { expected, found } => {
+                write!(f, "size {found} bytes (expected {expected})")
+            }
+        }
+    }
+}
+
+#[repr(C)]
 pub struct BootInfo {
+    pub magic: u64,
+    pub version: u32,
+    pub size: u32,
+    pub flags: BootInfoFlags,
+    /// Hypervisor options; the guest's own cmdline lives in `guest`.
+    pub cmdline: *const u8,
+    pub cmdline_len: u64,
     pub usable_memory: UsableMemory,
-    pub frame_buffer: Option<FrameBuffer>,
-    pub rsdp: Option<u64>,
-    pub bzimage_addr: u64,
-    pub bzimage_size: u64,
-    pub rootfs_addr: u64,
-    pub rootfs_size: u64,
+    pub memory_map: MemoryMap,
+    pub frame_buffer: FrameBuffer,
+    /// ACPI 2.0+ RSDP when the firmware has one, otherwise the ACPI 1.0 RSDP.
+    pub rsdp: u64,
+    /// SMBIOS 2.x (`_SM_`) entry point.
+    pub smbios: u64,
+    /// SMBIOS 3.x (`_SM3_`) entry point.
+    pub smbios3: u64,
+    pub efi_system_table: u64,
+    /// `crash::CRASH_LOG_SIZE` bytes, left as the previous boot wrote them.
+    pub crash_log: u64,
+    pub modules: ModuleList,
+    pub guest: GuestConfig,
+    pub measurements: MeasurementList,
+    pub kernel_image: KernelImage,
+}
+
+impl BootInfo {
+    pub const SIZE: u32 = core::mem::size_of::<Self>() as u32;
+
+    /// Must be called before any field past the header is read.
+    pub fn validate(&self) -> Result<(), BootInfoError> {
+        if self.magic != BOOT_INFO_MAGIC {
+            return Err(BootInfoError::BadMagic(self.magic));
+        }
+        if self.version != BOOT_INFO_VERSION {
+            return Err(BootInfoError::VersionMismatch {
+                expected: BOOT_INFO_VERSION,
+                found: self.version,
+            });
+        }
+        if self.size != Self::SIZE {
+            return Err(BootInfoError::SizeMismatch {
+                expected: Self::SIZE,
+                found: self.size,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn has(&self, flags: BootInfoFlags) -> bool {
+        self.flags.contains(flags)
+    }
+
+    pub fn cmdline(&self) -> &str {
+        if self.has(BootInfoFlags::CMDLINE) {
+            str_from_raw(self.cmdline, self.cmdline_len)
+        } else {
+            ""
+        }
+    }
+
+    pub fn memory_map(&self) -> &[MemoryRegion] {
+        if self.has(BootInfoFlags::MEMORY_MAP) {
+            self.memory_map.regions()
+        } else {
+            &[]
+        }
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        if self.has(BootInfoFlags::MODULES) {
+            self.modules.modules()
+        } else {
+            &[]
+        }
+    }
+
+    pub fn measurements(&self) -> &[Measurement] {
+        if self.has(BootInfoFlags::MEASUREMENTS) {
+            self.measurements.measurements()
+        } else {
+            &[]
+        }
+    }
+
+    pub fn kernel_image(&self) -> Option<&KernelImage> {
+        self.has(BootInfoFlags::KERNEL_IMAGE)
+            .then_some(&self.kernel_image)
+    }
+
+    pub fn frame_buffer(&self) -> Option<&FrameBuffer> {
+        self.has(BootInfoFlags::FRAME_BUFFER)
+            .then_some(&self.frame_buffer)
+    }
+
+    pub fn rsdp(&self) -> Option<u64> {
+        self.has(BootInfoFlags::RSDP).then_some(self.rsdp)
+    }
+
+    pub fn smbios(&self) -> Option<u64> {
+        self.has(BootInfoFlags::SMBIOS).then_some(self.smbios)
+    }
+
+    pub fn smbios3(&self) -> Option<u64> {
+        self.has(BootInfoFlags::SMBIOS3).then_some(self.smbios3)
+    }
+
+    pub fn crash_log(&self) -> Option<u64> {
+        self.has(BootInfoFlags::CRASH_LOG).then_some(self.crash_log)
+    }
+
+    pub fn efi_system_table(&self) -> Option<u64> {
+        self.has(BootInfoFlags::EFI_SYSTEM_TABLE)
+            .then_some(self.efi_system_table)
+    }
+
+    pub fn guest(&self) -> Option<&GuestConfig> {
+        self.has(BootInfoFlags::GUEST).then_some(&self.guest)
+    }
+}
+
+pub(crate) fn str_from_raw<'a>(ptr: *const u8, len: u64) -> &'a str {
+    if ptr.is_null() || len == 0 {
+        return "";
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len as usize) };
+    core::str::from_utf8(bytes).unwrap_or_default()
 }
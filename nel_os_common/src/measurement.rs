@@ -0,0 +1,51 @@
+use core::fmt;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub name: *const u8,
+    pub name_len: u64,
+    pub sha256: [u8; 32],
+}
+
+impl Measurement {
+    pub fn new(name: &'static str, sha256: [u8; 32]) -> Self {
+        Self {
+            name: name.as_ptr(),
+            name_len: name.len() as u64,
+            sha256,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        crate::str_from_raw(self.name, self.name_len)
+    }
+}
+
+#[repr(C)]
+pub struct MeasurementList {
+    pub measurements: *const Measurement,
+    pub len: u64,
+}
+
+impl MeasurementList {
+    pub fn measurements(&self) -> &[Measurement] {
+        if self.measurements.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.measurements, self.len as usize) }
+    }
+}
+
+pub struct HexDigest<'a>(pub &'a [u8]);
+
+impl fmt::Display for HexDigest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -15,3 +15,80 @@ impl UsableMemory {
         unsafe { core::slice::from_raw_parts(self.ranges, self.len as usize) }
     }
 }
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    Usable = 1,
+    Reserved = 2,
+    AcpiReclaim = 3,
+    AcpiNvs = 4,
+    Mmio = 5,
+    RuntimeServicesCode = 6,
+    RuntimeServicesData = 7,
+    Bootloader = 8,
+    Unusable = 9,
+    Persistent = 10,
+    /// Kept across warm resets for `crash::CrashRecord`.
+    CrashLog = 11,
+}
+
+impl MemoryRegionKind {
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            1 => Some(Self::Usable),
+            2 => Some(Self::Reserved),
+            3 => Some(Self::AcpiReclaim),
+            4 => Some(Self::AcpiNvs),
+            5 => Some(Self::Mmio),
+            6 => Some(Self::RuntimeServicesCode),
+            7 => Some(Self::RuntimeServicesData),
+            8 => Some(Self::Bootloader),
+            9 => Some(Self::Unusable),
+            10 => Some(Self::Persistent),
+            11 => Some(Self::CrashLog),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub end: u64,
+    pub kind: u32,
+    pub _reserved: u32,
+}
+
+impl MemoryRegion {
+    pub fn new(start: u64, end: u64, kind: MemoryRegionKind) -> Self {
+        Self {
+            start,
+            end,
+            kind: kind as u32,
+            _reserved: 0,
+        }
+    }
+
+    /// Unknown kinds from a newer bootloader are treated as reserved.
+    pub fn kind(&self) -> MemoryRegionKind {
+        MemoryRegionKind::from_raw(self.kind).unwrap_or(MemoryRegionKind::Reserved)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+#[repr(C)]
+pub struct MemoryMap {
+    pub regions: *const MemoryRegion,
+    pub len: u64,
+}
+
+impl MemoryMap {
+    pub fn regions(&self) -> &[MemoryRegion] {
+        unsafe { core::slice::from_raw_parts(self.regions, self.len as usize) }
+    }
+}
@@ -0,0 +1,109 @@
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    Kernel = 1,
+    Initrd = 2,
+    Firmware = 3,
+    Disk = 4,
+    Symbols = 5,
+    Other = 6,
+    /// Copied into the hypervisor's cpio overlay at the path given by its name.
+    Overlay = 7,
+}
+
+impl ModuleKind {
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            1 => Some(Self::Kernel),
+            2 => Some(Self::Initrd),
+            3 => Some(Self::Firmware),
+            4 => Some(Self::Disk),
+            5 => Some(Self::Symbols),
+            6 => Some(Self::Other),
+            7 => Some(Self::Overlay),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kernel" => Some(Self::Kernel),
+            "initrd" => Some(Self::Initrd),
+            "firmware" => Some(Self::Firmware),
+            "disk" => Some(Self::Disk),
+            "symbols" => Some(Self::Symbols),
+            "other" => Some(Self::Other),
+            "overlay" => Some(Self::Overlay),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Kernel => "kernel",
+            Self::Initrd => "initrd",
+            Self::Firmware => "firmware",
+            Self::Disk => "disk",
+            Self::Symbols => "symbols",
+            Self::Other => "other",
+            Self::Overlay => "overlay",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    pub name: *const u8,
+    pub name_len: u64,
+    pub addr: u64,
+    pub size: u64,
+    pub kind: u32,
+    pub _reserved: u32,
+}
+
+impl Module {
+    pub fn new(name: &'static str, addr: u64, size: u64, kind: ModuleKind) -> Self {
+        Self {
+            name: name.as_ptr(),
+            name_len: name.len() as u64,
+            addr,
+            size,
+            kind: kind as u32,
+            _reserved: 0,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        crate::str_from_raw(self.name, self.name_len)
+    }
+
+    /// Unknown kinds from a newer bootloader are treated as `Other`.
+    pub fn kind(&self) -> ModuleKind {
+        ModuleKind::from_raw(self.kind).unwrap_or(ModuleKind::Other)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        if self.addr == 0 || self.size == 0 {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.addr as *const u8, self.size as usize) }
+    }
+}
+
+#[repr(C)]
+pub struct ModuleList {
+    pub modules: *const Module,
+    pub len: u64,
+}
+
+impl ModuleList {
+    pub fn modules(&self) -> &[Module] {
+        if self.modules.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.modules, self.len as usize) }
+    }
+}
@@ -0,0 +1,127 @@
+//! Frame-pointer backtraces, symbolized with the function table the
+//! bootloader extracts from the kernel ELF.
+
+use core::{arch::asm, fmt};
+
+use nel_os_common::image::KernelImage;
+use spin::Once;
+use x86_64::VirtAddr;
+
+use crate::{memory::paging, println};
+
+const MAX_FRAMES: usize = 32;
+
+struct Symbols(&'static KernelImage);
+
+// The image description lives in bootloader memory, which is never freed
+// and never written after boot.
+unsafe impl Send for Symbols {}
+unsafe impl Sync for Symbols {}
+
+static SYMBOLS: Once<Symbols> = Once::new();
+
+pub fn init(image: &'static KernelImage) {
+    SYMBOLS.call_once(|| Symbols(image));
+}
+
+pub fn symbolize(addr: u64) -> Option<(&'static str, u64)> {
+    SYMBOLS.get()?.0.symbolize(addr)
+}
+
+fn is_mapped(addr: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| paging::translate_addr(addr).is_some())
+}
+
+/// Return addresses found by following the saved `rbp` chain from `rbp`.
+/// Stops at a null, unmapped, misaligned or non-ascending frame pointer.
+pub fn frames(mut rbp: u64) -> impl Iterator<Item = u64> {
+    core::iter::from_fn(move || {
+        if rbp == 0 || rbp % 8 != 0 || !is_mapped(rbp) || !is_mapped(rbp + 8) {
+            return None;
+        }
+
+        let (next, return_addr) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if return_addr == 0 {
+            return None;
+        }
+        rbp = if next > rbp { next } else { 0 };
+
+        Some(return_addr)
+    })
+    .take(MAX_FRAMES)
+}
+
+/// One symbolized line of a backtrace.
+struct Frame {
+    index: usize,
+    addr: u64,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match symbolize(self.addr) {
+            Some((name, offset)) => {
+                write!(
+                    f,
+                    "  #{:<2} {:#018x} {}+{:#x}",
+                    self.index, self.addr, name, offset
+                )
+            }
+            None => write!(f, "  #{:<2} {:#018x} ??", self.index, self.addr),
+        }
+    }
+}
+
+pub fn print_frame(index: usize, addr: u64) {
+    println!("{}", Frame { index, addr });
+}
+
+/// Like `print`, but into `out`; neither locks nor allocates.
+pub fn write(out: &mut impl fmt::Write, rip: u64, rbp: u64) -> fmt::Result {
+    writeln!(out, "Backtrace:")?;
+    writeln!(
+        out,
+        "{}",
+        Frame {
+            index: 0,
+            addr: rip
+        }
+    )?;
+    for (index, addr) in frames(rbp).enumerate() {
+        let frame = Frame {
+            index: index + 1,
+            addr: addr.saturating_sub(1),
+        };
+        writeln!(out, "{frame}")?;
+    }
+    Ok(())
+}
+
+/// Prints `rip` and then every caller reachable from `rbp`.
+pub fn print(rip: u64, rbp: u64) {
+    println!("Backtrace:");
+    print_frame(0, rip);
+    for (index, addr) in frames(rbp).enumerate() {
+        // Return addresses point after the call; look up the call itself.
+        print_frame(index + 1, addr.saturating_sub(1));
+    }
+}
+
+/// Frame pointer of the calling function.
+#[inline(always)]
+pub fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+/// Backtrace of the caller.
+#[inline(never)]
+pub fn print_current() {
+    let rbp = current_rbp();
+
+    println!("Backtrace:");
+    for (index, addr) in frames(rbp).enumerate() {
+        print_frame(index, addr.saturating_sub(1));
+    }
+}
@@ -9,8 +9,16 @@ pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
 pub const KERNEL_STACK_SIZE: usize = 1024 * 1024;
+pub const AP_STACK_SIZE: usize = 256 * 1024;
+/// Physical memory is identity mapped; the hypervisor hands physical
+/// addresses of VMCS, EPT and guest frames straight to the CPU and to Rust.
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0;
 pub const HEAP_START: usize = 0x4444_4444_0000;
 pub const HEAP_SIZE: usize = 128 * 1024;
+pub const HEAP_GROW_SIZE: usize = 64 * 1024;
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+pub const DEFAULT_GUEST_MEMORY_SIZE: u64 = 256 * 1024 * 1024;
 
 pub const PAGE_SIZE: usize = 4096;
 pub const BITS_PER_ENTRY: usize = 8 * core::mem::size_of::<usize>();
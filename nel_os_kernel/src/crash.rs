@@ -0,0 +1,302 @@
+//! Crash log in the fixed physical region the bootloader reserves. Fatal
+//! exceptions and panics leave a `CrashRecord` there, and the next boot
+//! reports it if the firmware kept memory intact across the reset.
+//!
+//! The recording path runs in whatever state the kernel died in: it takes
+//! no locks, does not allocate and does not print.
+
+use core::{
+    fmt,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+use nel_os_common::{
+    crash::{CrashRecord, CrashRegisters, CrashVmExit, CRASH_VECTOR_PANIC, CRASH_VM_EXITS_LEN},
+    BootInfo,
+};
+use spin::Once;
+use x86_64::registers::control::{Cr2, Cr3};
+
+use crate::{
+    backtrace,
+    interrupt::exception::{self, ExceptionFrame},
+    println, time,
+    time::Duration,
+    warn,
+};
+
+static CRASH_LOG: Once<u64> = Once::new();
+/// Whether the region held a valid record from the previous boot at `init`.
+static PREVIOUS: Once<bool> = Once::new();
+
+const IDLE: u32 = 0;
+/// An exception record is sealed; the panic that follows adds its message.
+const EXCEPTION: u32 = 1;
+const SEALED: u32 = 2;
+
+static STATE: AtomicU32 = AtomicU32::new(IDLE);
+
+struct VmExitSlot {
+    reason: AtomicU32,
+    qualification: AtomicU64,
+    guest_rip: AtomicU64,
+    uptime_ns: AtomicU64,
+}
+
+impl VmExitSlot {
+    const fn new() -> Self {
+        Self {
+            reason: AtomicU32::new(0),
+            qualification: AtomicU64::new(0),
+            guest_rip: AtomicU64::new(0),
+            uptime_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+static VM_EXITS: [VmExitSlot; CRASH_VM_EXITS_LEN] =
+    [const { VmExitSlot::new() }; CRASH_VM_EXITS_LEN];
+static VM_EXIT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts recording into the region from `boot_info`. A record left by the
+/// previous boot is kept until `report_previous`.
+pub fn init(boot_info: &BootInfo) {
+    let Some(addr) = boot_info.crash_log() else {
+        return;
+    };
+
+    let record = unsafe { &*(addr as *const CrashRecord) };
+    PREVIOUS.call_once(|| record.is_valid());
+    CRASH_LOG.call_once(|| addr);
+}
+
+/// Prints the previous boot's crash, if any, and clears the region.
+pub fn report_previous() {
+    let Some(&addr) = CRASH_LOG.get() else {
+        warn!("No crash log region");
+        return;
+    };
+    // A crash since `init` would have halted the kernel before this point.
+    let record = unsafe { &mut *(addr as *mut CrashRecord) };
+
+    if PREVIOUS.get() == Some(&true) && record.is_valid() {
+        print_record(record);
+    }
+    record.clear();
+}
+
+fn print_record(record: &CrashRecord) {
+    let uptime = Duration::from_nanos(record.uptime_ns).as_secs_f64();
+    if record.vector == CRASH_VECTOR_PANIC {
+        warn!(
+            "Previous boot panicked on CPU {} at {:.6}s",
+            record.cpu, uptime
+        );
+    } else {
+        warn!(
+            "Previous boot died of {} (error code {:#x}) on CPU {} at {:.6}s",
+            exception::name(record.vector as u8),
+            record.error_code,
+            record.cpu,
+            uptime
+        );
+    }
+    for line in record.message().lines() {
+        println!("  {}", line);
+    }
+
+    if record.has_registers != 0 {
+        let r = &record.registers;
+        println!("Registers:");
+        println!(
+            "  RAX={:016x} RBX={:016x} RCX={:016x} RDX={:016x}",
+            r.rax, r.rbx, r.rcx, r.rdx
+        );
+        println!(
+            "  RSI={:016x} RDI={:016x} RBP={:016x} RSP={:016x}",
+            r.rsi, r.rdi, r.rbp, r.rsp
+        );
+        println!(
+            "  R8 ={:016x} R9 ={:016x} R10={:016x} R11={:016x}",
+            r.r8, r.r9, r.r10, r.r11
+        );
+        println!(
+            "  R12={:016x} R13={:016x} R14={:016x} R15={:016x}",
+            r.r12, r.r13, r.r14, r.r15
+        );
+        println!(
+            "  RIP={:016x} RFLAGS={:016x} CS={:04x} SS={:04x}",
+            r.rip, r.rflags, r.cs, r.ss
+        );
+        println!("  CR2={:016x} CR3={:016x}", r.cr2, r.cr3);
+    }
+
+    // The symbol table of this boot's kernel; right as long as it is unchanged.
+    println!("Backtrace:");
+    for (index, &addr) in record.backtrace().iter().enumerate() {
+        backtrace::print_frame(index, addr);
+    }
+
+    if !record.vm_exits().is_empty() {
+        println!("Last VM exits (oldest first):");
+        for exit in record.vm_exits() {
+            println!(
+                "  {:.6}s reason={:#x} qualification={:#x} rip={:#x}",
+                Duration::from_nanos(exit.uptime_ns).as_secs_f64(),
+                exit.reason,
+                exit.qualification,
+                exit.guest_rip
+            );
+        }
+    }
+}
+
+/// Remembers a VM exit for the next crash record. Cheap enough for every exit.
+pub fn record_vm_exit(reason: u32, qualification: u64, guest_rip: u64) {
+    let index = VM_EXIT_COUNT.fetch_add(1, Ordering::Relaxed);
+    let slot = &VM_EXITS[index % CRASH_VM_EXITS_LEN];
+
+    slot.reason.store(reason, Ordering::Relaxed);
+    slot.qualification.store(qualification, Ordering::Relaxed);
+    slot.guest_rip.store(guest_rip, Ordering::Relaxed);
+    slot.uptime_ns
+        .store(time::uptime().as_nanos() as u64, Ordering::Relaxed);
+}
+
+fn record() -> Option<&'static mut CrashRecord> {
+    CRASH_LOG
+        .get()
+        .map(|&addr| unsafe { &mut *(addr as *mut CrashRecord) })
+}
+
+fn cpu_index() -> u32 {
+    crate::smp::percpu::current().map_or(0, |cpu| cpu.index as u32)
+}
+
+fn fill_backtrace(record: &mut CrashRecord, frames: impl Iterator<Item = u64>) {
+    let mut len = 0;
+    for (slot, addr) in record.backtrace.iter_mut().zip(frames) {
+        *slot = addr;
+        len += 1;
+    }
+    record.backtrace_len = len;
+}
+
+/// Saves and seals the state of a fatal exception, before the handler takes
+/// any lock. The panic it raises replaces the placeholder message.
+pub fn record_exception(frame: &ExceptionFrame) {
+    let Some(record) = record() else {
+        return;
+    };
+    if STATE
+        .compare_exchange(IDLE, EXCEPTION, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return;
+    }
+
+    record.clear();
+    record.cpu = cpu_index();
+    record.vector = frame.vector as u32;
+    record.error_code = frame.error_code;
+    record.has_registers = 1;
+    record.registers = CrashRegisters {
+        rax: frame.rax,
+        rbx: frame.rbx,
+        rcx: frame.rcx,
+        rdx: frame.rdx,
+        rsi: frame.rsi,
+        rdi: frame.rdi,
+        rbp: frame.rbp,
+        rsp: frame.rsp,
+        r8: frame.r8,
+        r9: frame.r9,
+        r10: frame.r10,
+        r11: frame.r11,
+        r12: frame.r12,
+        r13: frame.r13,
+        r14: frame.r14,
+        r15: frame.r15,
+        rip: frame.rip,
+        rflags: frame.rflags,
+        cs: frame.cs,
+        ss: frame.ss,
+        cr2: Cr2::read_raw(),
+        cr3: Cr3::read().0.start_address().as_u64(),
+    };
+    // Return addresses point after the call; keep the call itself.
+    let callers = backtrace::frames(frame.rbp).map(|addr| addr.saturating_sub(1));
+    fill_backtrace(record, core::iter::once(frame.rip).chain(callers));
+
+    complete(
+        record,
+        format_args!("EXCEPTION: {}", exception::name(frame.vector as u8)),
+    );
+}
+
+/// Completes and seals the record. Only the first CPU to die writes one.
+pub fn record_panic(info: &PanicInfo) {
+    let Some(record) = record() else {
+        return;
+    };
+    let cpu = cpu_index();
+
+    match STATE.compare_exchange(IDLE, SEALED, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {
+            record.clear();
+            record.cpu = cpu;
+            record.vector = CRASH_VECTOR_PANIC;
+            let callers =
+                backtrace::frames(backtrace::current_rbp()).map(|addr| addr.saturating_sub(1));
+            fill_backtrace(record, callers);
+        }
+        Err(EXCEPTION) if record.cpu == cpu => STATE.store(SEALED, Ordering::Release),
+        Err(_) => return,
+    }
+
+    complete(record, format_args!("{info}"));
+}
+
+/// Writes `message` and the recent VM exits, then seals the record.
+fn complete(record: &mut CrashRecord, message: fmt::Arguments) {
+    let mut writer = MessageWriter {
+        buffer: &mut record.message,
+        len: 0,
+    };
+    let _ = fmt::write(&mut writer, message);
+    record.message_len = writer.len as u32;
+
+    let count = VM_EXIT_COUNT.load(Ordering::Relaxed);
+    let recorded = count.min(CRASH_VM_EXITS_LEN);
+    for (i, exit) in record.vm_exits.iter_mut().take(recorded).enumerate() {
+        let slot = &VM_EXITS[(count - recorded + i) % CRASH_VM_EXITS_LEN];
+        *exit = CrashVmExit {
+            reason: slot.reason.load(Ordering::Relaxed),
+            _reserved: 0,
+            qualification: slot.qualification.load(Ordering::Relaxed),
+            guest_rip: slot.guest_rip.load(Ordering::Relaxed),
+            uptime_ns: slot.uptime_ns.load(Ordering::Relaxed),
+        };
+    }
+    record.vm_exit_count = recorded as u32;
+
+    record.uptime_ns = time::uptime().as_nanos() as u64;
+    record.seal();
+}
+
+/// Fills `buffer`, silently dropping what does not fit.
+struct MessageWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let buffer = &mut self.buffer[self.len..];
+        let count = s.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
@@ -13,6 +13,43 @@ lazy_static! {
 pub enum PixelFormat {
     Rgb,
     Bgr,
+    Bitmask {
+        red: Channel,
+        green: Channel,
+        blue: Channel,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn from_mask(mask: u32) -> Self {
+        if mask == 0 {
+            return Self { shift: 0, bits: 0 };
+        }
+
+        let shift = mask.trailing_zeros();
+        Self {
+            shift,
+            bits: (mask >> shift).trailing_ones(),
+        }
+    }
+
+    /// Scales an 8-bit intensity to the channel width and moves it into place.
+    fn encode(&self, value: u8) -> u32 {
+        let value = value as u32;
+        let scaled = match self.bits {
+            0 => return 0,
+            bits @ 1..8 => value >> (8 - bits),
+            bits => value << (bits - 8),
+        };
+
+        scaled << self.shift
+    }
 }
 
 pub struct FrameBuffer {
@@ -46,6 +83,11 @@ impl FrameBuffer {
             pixel_format: match raw_buffer.pixl_format {
                 RawPixelFormat::Rgb => PixelFormat::Rgb,
                 RawPixelFormat::Bgr => PixelFormat::Bgr,
+                RawPixelFormat::Bitmask => PixelFormat::Bitmask {
+                    red: Channel::from_mask(raw_buffer.mask.red),
+                    green: Channel::from_mask(raw_buffer.mask.green),
+                    blue: Channel::from_mask(raw_buffer.mask.blue),
+                },
             },
             background_color,
             text_cursor: (0, 0),
@@ -83,6 +125,11 @@ impl FrameBuffer {
                     *pixel_ptr.add(1) = g;
                     *pixel_ptr.add(2) = r;
                 }
+
+                PixelFormat::Bitmask { red, green, blue } => {
+                    let pixel = red.encode(r) | green.encode(g) | blue.encode(b);
+                    (pixel_ptr as *mut u32).write_unaligned(pixel);
+                }
             }
         }
     }
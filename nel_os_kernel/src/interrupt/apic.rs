@@ -1,11 +1,14 @@
 use acpi::PlatformInfo;
 use alloc::alloc::Global;
+use core::sync::atomic::{AtomicU32, Ordering};
 use spin::Once;
 use x86_64::instructions::port::Port;
 
 use crate::interrupt::idt::IRQ_TIMER;
 
 pub static LAPIC: Once<LocalApic> = Once::new();
+/// Timer count calibrated on the BSP, reused by every AP.
+static TIMER_INITIAL_COUNT: AtomicU32 = AtomicU32::new(0);
 
 pub fn disable_pic_8259() {
     unsafe {
@@ -35,8 +38,30 @@ impl LocalApic {
     pub fn write(&self, offset: u32, value: u32) {
         unsafe { self.ptr.add(offset as usize).write_volatile(value) }
     }
+
+    pub fn id(&self) -> u32 {
+        self.read(ID) >> 24
+    }
+
+    fn send_ipi(&self, apic_id: u32, command: u32) {
+        self.write(ICRHI, apic_id << 24);
+        self.write(ICRLO, command);
+
+        while self.read(ICRLO) & DELIVS != 0 {}
+    }
+
+    pub fn send_init(&self, apic_id: u32) {
+        self.send_ipi(apic_id, INIT | LEVEL | ASSERT);
+        self.send_ipi(apic_id, INIT | LEVEL);
+    }
+
+    /// Starts `apic_id` in real mode at `vector << 12`.
+    pub fn send_startup(&self, apic_id: u32, vector: u8) {
+        self.send_ipi(apic_id, STARTUP | vector as u32);
+    }
 }
 
+const ID: u32 = 0x0020 / 4;
 const SVR: u32 = 0x00f0 / 4;
 const ENABLE: u32 = 0x100;
 
@@ -51,8 +76,9 @@ const PERIODIC: u32 = 0x20000;
 const MASKED: u32 = 0x10000;
 
 const ICRLO: u32 = 0x0300 / 4;
-const BCAST: u32 = 0x80000;
 const INIT: u32 = 0x500;
+const STARTUP: u32 = 0x600;
+const ASSERT: u32 = 0x4000;
 const LEVEL: u32 = 0x8000;
 const DELIVS: u32 = 0x1000;
 
@@ -68,7 +94,7 @@ const TPR: u32 = 0x0080 / 4;
 
 const PM_TIMER_FREQ: usize = 3579545;
 
-pub fn init_local_apic(platform_info: PlatformInfo<'_, Global>) {
+pub fn init_local_apic(platform_info: &PlatformInfo<'_, Global>) {
     disable_pic_8259();
 
     let apic_info = match platform_info.interrupt_model {
@@ -88,6 +114,7 @@ pub fn init_local_apic(platform_info: PlatformInfo<'_, Global>) {
     local_apic.write(TICR, u32::MAX);
     let pm_timer = platform_info
         .pm_timer
+        .as_ref()
         .expect("PM Timer not found in ACPI tables");
     let mut time = Port::<u32>::new(pm_timer.base.address as u16);
     let start = unsafe { time.read() };
@@ -102,9 +129,23 @@ pub fn init_local_apic(platform_info: PlatformInfo<'_, Global>) {
     let local_apic_freq = u32::MAX - local_apic.read(TCCR);
     local_apic.write(TICR, 0);
 
+    // Counted over 100ms; tick every millisecond.
+    TIMER_INITIAL_COUNT.store(local_apic_freq / 100, Ordering::Relaxed);
+    start_local_apic(&local_apic);
+}
+
+/// Enables the local APIC of an AP with the timer calibrated on the BSP.
+pub fn init_ap_local_apic() {
+    let local_apic = LAPIC.get().expect("BSP local APIC is not initialized");
+
+    local_apic.write(SVR, ENABLE | 0xff);
+    start_local_apic(local_apic);
+}
+
+fn start_local_apic(local_apic: &LocalApic) {
     local_apic.write(TDCR, X1);
     local_apic.write(TIMER, PERIODIC | IRQ_TIMER);
-    local_apic.write(TICR, local_apic_freq / 250);
+    local_apic.write(TICR, TIMER_INITIAL_COUNT.load(Ordering::Relaxed));
 
     local_apic.write(LINT0, MASKED);
     local_apic.write(LINT1, MASKED);
@@ -114,11 +155,5 @@ pub fn init_local_apic(platform_info: PlatformInfo<'_, Global>) {
     }
 
     local_apic.write(EOI, 0);
-
-    local_apic.write(ICRHI, 0);
-    local_apic.write(ICRLO, BCAST | INIT | LEVEL);
-
-    while local_apic.read(ICRLO) & DELIVS != 0 {}
-
     local_apic.write(TPR, 0);
 }
@@ -0,0 +1,341 @@
+//! Entry stubs for the architectural exceptions. Each stub pushes a uniform
+//! `ExceptionFrame` (a zero error code where the CPU pushes none) and calls
+//! `exception_handler`, so every exception can dump the full register state.
+
+use core::{
+    arch::global_asm,
+    fmt::{self, Write},
+};
+
+use spin::MutexGuard;
+use uart_16550::SerialPort;
+use x86_64::{
+    registers::control::{Cr0, Cr2, Cr3, Cr4},
+    structures::idt::PageFaultErrorCode,
+};
+
+use crate::{
+    backtrace, crash, error, interrupt::subscriber::InterruptContext, logging::dmesg, serial, time,
+    warn,
+};
+
+global_asm!(
+    r#"
+    .macro EXCEPTION_STUB vector, has_error_code
+    .global exception_stub_\vector
+exception_stub_\vector:
+    .if \has_error_code == 0
+    push 0
+    .endif
+    push \vector
+    jmp exception_common
+    .endm
+
+    .pushsection .text
+    EXCEPTION_STUB 0, 0
+    EXCEPTION_STUB 1, 0
+    EXCEPTION_STUB 2, 0
+    EXCEPTION_STUB 3, 0
+    EXCEPTION_STUB 4, 0
+    EXCEPTION_STUB 5, 0
+    EXCEPTION_STUB 6, 0
+    EXCEPTION_STUB 7, 0
+    EXCEPTION_STUB 8, 1
+    EXCEPTION_STUB 10, 1
+    EXCEPTION_STUB 11, 1
+    EXCEPTION_STUB 12, 1
+    EXCEPTION_STUB 13, 1
+    EXCEPTION_STUB 14, 1
+    EXCEPTION_STUB 16, 0
+    EXCEPTION_STUB 17, 1
+    EXCEPTION_STUB 18, 0
+    EXCEPTION_STUB 19, 0
+    EXCEPTION_STUB 20, 0
+    EXCEPTION_STUB 21, 1
+    EXCEPTION_STUB 28, 0
+    EXCEPTION_STUB 29, 1
+    EXCEPTION_STUB 30, 1
+
+exception_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rdi, rsp
+    cld
+    call {handler}
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 16
+    iretq
+    .popsection
+"#,
+    handler = sym exception_handler,
+);
+
+unsafe extern "C" {
+    fn exception_stub_0();
+    fn exception_stub_1();
+    fn exception_stub_2();
+    fn exception_stub_3();
+    fn exception_stub_4();
+    fn exception_stub_5();
+    fn exception_stub_6();
+    fn exception_stub_7();
+    fn exception_stub_8();
+    fn exception_stub_10();
+    fn exception_stub_11();
+    fn exception_stub_12();
+    fn exception_stub_13();
+    fn exception_stub_14();
+    fn exception_stub_16();
+    fn exception_stub_17();
+    fn exception_stub_18();
+    fn exception_stub_19();
+    fn exception_stub_20();
+    fn exception_stub_21();
+    fn exception_stub_28();
+    fn exception_stub_29();
+    fn exception_stub_30();
+}
+
+/// Entry point of the stub for `vector`, or `None` for reserved vectors.
+pub fn stub(vector: u8) -> Option<u64> {
+    let stub: unsafe extern "C" fn() = match vector {
+        0 => exception_stub_0,
+        1 => exception_stub_1,
+        2 => exception_stub_2,
+        3 => exception_stub_3,
+        4 => exception_stub_4,
+        5 => exception_stub_5,
+        6 => exception_stub_6,
+        7 => exception_stub_7,
+        8 => exception_stub_8,
+        10 => exception_stub_10,
+        11 => exception_stub_11,
+        12 => exception_stub_12,
+        13 => exception_stub_13,
+        14 => exception_stub_14,
+        16 => exception_stub_16,
+        17 => exception_stub_17,
+        18 => exception_stub_18,
+        19 => exception_stub_19,
+        20 => exception_stub_20,
+        21 => exception_stub_21,
+        28 => exception_stub_28,
+        29 => exception_stub_29,
+        30 => exception_stub_30,
+        _ => return None,
+    };
+
+    Some(stub as usize as u64)
+}
+
+pub const DEBUG: u8 = 1;
+pub const NMI: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const PAGE_FAULT: u8 = 14;
+pub const MACHINE_CHECK: u8 = 18;
+
+/// Register state saved by the stubs, lowest address first.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct ExceptionFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl fmt::Display for ExceptionFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            [("RAX", self.rax), ("RBX", self.rbx), ("RCX", self.rcx)],
+            [("RDX", self.rdx), ("RSI", self.rsi), ("RDI", self.rdi)],
+            [("RBP", self.rbp), ("RSP", self.rsp), ("R8", self.r8)],
+            [("R9", self.r9), ("R10", self.r10), ("R11", self.r11)],
+            [("R12", self.r12), ("R13", self.r13), ("R14", self.r14)],
+            [
+                ("R15", self.r15),
+                ("RIP", self.rip),
+                ("RFLAGS", self.rflags),
+            ],
+        ];
+        for row in rows {
+            for (name, value) in row {
+                write!(f, "{name:>6}={value:016x} ")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "    CS={:04x} SS={:04x} ERR={:#x}",
+            self.cs, self.ss, self.error_code
+        )?;
+        write!(
+            f,
+            "   CR0={:016x}    CR2={:016x}    CR3={:016x}    CR4={:016x}",
+            Cr0::read_raw(),
+            Cr2::read_raw(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
+
+pub fn name(vector: u8) -> &'static str {
+    match vector {
+        0 => "DIVIDE ERROR",
+        1 => "DEBUG",
+        2 => "NON-MASKABLE INTERRUPT",
+        3 => "BREAKPOINT",
+        4 => "OVERFLOW",
+        5 => "BOUND RANGE EXCEEDED",
+        6 => "INVALID OPCODE",
+        7 => "DEVICE NOT AVAILABLE",
+        8 => "DOUBLE FAULT",
+        10 => "INVALID TSS",
+        11 => "SEGMENT NOT PRESENT",
+        12 => "STACK-SEGMENT FAULT",
+        13 => "GENERAL PROTECTION FAULT",
+        14 => "PAGE FAULT",
+        16 => "X87 FLOATING-POINT ERROR",
+        17 => "ALIGNMENT CHECK",
+        18 => "MACHINE CHECK",
+        19 => "SIMD FLOATING-POINT EXCEPTION",
+        20 => "VIRTUALIZATION EXCEPTION",
+        21 => "CONTROL PROTECTION EXCEPTION",
+        28 => "HYPERVISOR INJECTION EXCEPTION",
+        29 => "VMM COMMUNICATION EXCEPTION",
+        30 => "SECURITY EXCEPTION",
+        _ => "RESERVED",
+    }
+}
+
+extern "sysv64" fn exception_handler(frame: &mut ExceptionFrame) {
+    let vector = frame.vector as u8;
+    let context = InterruptContext {
+        vector,
+        instruction_pointer: frame.rip,
+        code_segment: frame.cs,
+        cpu_flags: frame.rflags,
+        stack_pointer: frame.rsp,
+        stack_segment: frame.ss,
+    };
+
+    match vector {
+        // Traps that leave the interrupted code able to continue.
+        DEBUG | BREAKPOINT => {
+            crate::interrupt::subscriber::try_dispatch_to_subscribers(&context);
+            warn!("EXCEPTION: {} at {:#x}", name(vector), frame.rip);
+            return;
+        }
+        // These arrive even with interrupts off, so this CPU may hold any
+        // lock; subscribers, the logger and `print!` could all deadlock.
+        NMI => {
+            report_unlocked(frame, 'W');
+            return;
+        }
+        MACHINE_CHECK => {
+            crash::record_exception(frame);
+            report_unlocked(frame, 'E');
+            panic!("EXCEPTION: {}", name(vector));
+        }
+        _ => {
+            crash::record_exception(frame);
+            crate::interrupt::subscriber::try_dispatch_to_subscribers(&context);
+        }
+    }
+
+    error!("EXCEPTION: {} on CPU {}", name(vector), cpu_index());
+    if vector == PAGE_FAULT {
+        error!(
+            "Accessed address: {:#x} ({:?})",
+            Cr2::read_raw(),
+            PageFaultErrorCode::from_bits_truncate(frame.error_code)
+        );
+    }
+    error!("Registers:\n{}", frame);
+    backtrace::print(frame.rip, frame.rbp);
+
+    panic!("EXCEPTION: {}", name(vector));
+}
+
+fn cpu_index() -> usize {
+    crate::smp::percpu::current().map_or(0, |cpu| cpu.index)
+}
+
+/// Writes to the dmesg ring, and to the serial port if no one holds it.
+struct UnlockedConsole<'a> {
+    serial: Option<MutexGuard<'a, SerialPort>>,
+}
+
+impl Write for UnlockedConsole<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        dmesg::write(s.as_bytes());
+        if let Some(serial) = &mut self.serial {
+            for &byte in s.as_bytes() {
+                serial.send(byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports `frame` in the logger's line format without taking any lock.
+fn report_unlocked(frame: &ExceptionFrame, letter: char) {
+    let mut console = UnlockedConsole {
+        serial: serial::SERIAL1.try_lock(),
+    };
+    let uptime = time::uptime().as_secs_f64();
+
+    let _ = writeln!(
+        console,
+        "[{uptime:>12.5} {letter}] EXCEPTION: {} on CPU {}\n{frame}",
+        name(frame.vector as u8),
+        cpu_index()
+    );
+    let _ = backtrace::write(&mut console, frame.rip, frame.rbp);
+}
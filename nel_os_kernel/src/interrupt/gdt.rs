@@ -1,3 +1,4 @@
+use alloc::boxed::Box;
 use lazy_static::lazy_static;
 use x86_64::{
     instructions::tables::load_tss,
@@ -9,39 +10,61 @@ use x86_64::{
     VirtAddr,
 };
 
+use crate::memory::stack::GuardedStack;
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+/// Stacks in the interrupt stack table, indexed by the constants above.
+pub const IST_STACK_COUNT: usize = 3;
+pub const IST_STACK_SIZE: usize = 4096 * 5;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
+static mut DOUBLE_FAULT_STACK: GuardedStack<IST_STACK_SIZE> = GuardedStack::new();
+static mut NMI_STACK: GuardedStack<IST_STACK_SIZE> = GuardedStack::new();
+static mut MACHINE_CHECK_STACK: GuardedStack<IST_STACK_SIZE> = GuardedStack::new();
 
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+pub fn ist_stack_guards() -> [u64; IST_STACK_COUNT] {
+    [
+        GuardedStack::guard_page(&raw const DOUBLE_FAULT_STACK),
+        GuardedStack::guard_page(&raw const NMI_STACK),
+        GuardedStack::guard_page(&raw const MACHINE_CHECK_STACK),
+    ]
+}
 
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            stack_start + STACK_SIZE as u64
-        };
-        tss
-    };
+lazy_static! {
+    static ref TSS: TaskStateSegment = new_tss([
+        VirtAddr::new(GuardedStack::top(&raw const DOUBLE_FAULT_STACK)),
+        VirtAddr::new(GuardedStack::top(&raw const NMI_STACK)),
+        VirtAddr::new(GuardedStack::top(&raw const MACHINE_CHECK_STACK)),
+    ]);
+}
+
+fn new_tss(ist_stack_tops: [VirtAddr; IST_STACK_COUNT]) -> TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    let mut table = tss.interrupt_stack_table;
+    table[..IST_STACK_COUNT].copy_from_slice(&ist_stack_tops);
+    tss.interrupt_stack_table = table;
+    tss
 }
 
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
+}
 
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                data_selector,
-                tss_selector,
-            },
-        )
-    };
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let data_selector = gdt.append(Descriptor::kernel_data_segment());
+    let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            data_selector,
+            tss_selector,
+        },
+    )
 }
 
 struct Selectors {
@@ -51,15 +74,27 @@ struct Selectors {
 }
 
 pub fn init() {
-    GDT.0.load();
+    load(&GDT);
+}
+
+/// Gives an AP its own GDT and TSS, with IST stacks ending at
+/// `ist_stack_tops`. Needs the heap.
+pub fn init_ap(ist_stack_tops: [VirtAddr; IST_STACK_COUNT]) {
+    let tss = Box::leak(Box::new(new_tss(ist_stack_tops)));
+
+    load(Box::leak(Box::new(new_gdt(tss))));
+}
+
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
+    gdt.0.load();
 
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        DS::set_reg(GDT.1.data_selector);
-        ES::set_reg(GDT.1.data_selector);
-        FS::set_reg(GDT.1.data_selector);
-        GS::set_reg(GDT.1.data_selector);
-        SS::set_reg(GDT.1.data_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(gdt.1.code_selector);
+        DS::set_reg(gdt.1.data_selector);
+        ES::set_reg(gdt.1.data_selector);
+        FS::set_reg(gdt.1.data_selector);
+        GS::set_reg(gdt.1.data_selector);
+        SS::set_reg(gdt.1.data_selector);
+        load_tss(gdt.1.tss_selector);
     }
 }
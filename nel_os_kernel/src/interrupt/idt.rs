@@ -1,86 +1,93 @@
 use lazy_static::lazy_static;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{
+    structures::idt::{
+        Entry, EntryOptions, HandlerFunc, InterruptDescriptorTable, InterruptStackFrame,
+    },
+    VirtAddr,
+};
 
 use crate::{
     interrupt::{
         apic::{EOI, LAPIC},
-        gdt,
+        exception, gdt,
         subscriber::InterruptContext,
     },
-    time, warn,
+    smp::percpu,
+    time,
 };
 
 const PIC_8259_IRQ_OFFSET: u32 = 32;
 pub const IRQ_TIMER: u32 = PIC_8259_IRQ_OFFSET + 16;
+/// Vectors that IOAPIC inputs can be routed to.
+pub const IRQ_EXTERNAL_BASE: u8 = 0x40;
+pub const IRQ_EXTERNAL_COUNT: u8 = 24;
+
+macro_rules! external_irq_handlers {
+    ($($offset:literal)*) => {
+        [$(external_irq_handler::<{ IRQ_EXTERNAL_BASE + $offset }> as HandlerFunc),*]
+    };
+}
+
+const EXTERNAL_IRQ_HANDLERS: [HandlerFunc; IRQ_EXTERNAL_COUNT as usize] =
+    external_irq_handlers!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23);
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint
-            .set_handler_fn(breakpoint_handler)
-            .disable_interrupts(true);
-        unsafe {
-            idt.double_fault
-                .set_handler_fn(double_fault_handler)
-                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX)
-                .disable_interrupts(true);
-        }
-        idt.page_fault
-            .set_handler_fn(page_fault_handler)
-            .disable_interrupts(true);
+        set_exception_stub(&mut idt.divide_error, 0);
+        set_exception_stub(&mut idt.debug, 1);
+        let nmi = set_exception_stub(&mut idt.non_maskable_interrupt, 2);
+        unsafe { nmi.set_stack_index(gdt::NMI_IST_INDEX) };
+        set_exception_stub(&mut idt.breakpoint, 3);
+        set_exception_stub(&mut idt.overflow, 4);
+        set_exception_stub(&mut idt.bound_range_exceeded, 5);
+        set_exception_stub(&mut idt.invalid_opcode, 6);
+        set_exception_stub(&mut idt.device_not_available, 7);
+        let double_fault = set_exception_stub(&mut idt.double_fault, 8);
+        unsafe { double_fault.set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX) };
+        set_exception_stub(&mut idt.invalid_tss, 10);
+        set_exception_stub(&mut idt.segment_not_present, 11);
+        set_exception_stub(&mut idt.stack_segment_fault, 12);
+        set_exception_stub(&mut idt.general_protection_fault, 13);
+        set_exception_stub(&mut idt.page_fault, 14);
+        set_exception_stub(&mut idt.x87_floating_point, 16);
+        set_exception_stub(&mut idt.alignment_check, 17);
+        let machine_check = set_exception_stub(&mut idt.machine_check, 18);
+        unsafe { machine_check.set_stack_index(gdt::MACHINE_CHECK_IST_INDEX) };
+        set_exception_stub(&mut idt.simd_floating_point, 19);
+        set_exception_stub(&mut idt.virtualization, 20);
+        set_exception_stub(&mut idt.cp_protection_exception, 21);
+        set_exception_stub(&mut idt.hv_injection_exception, 28);
+        set_exception_stub(&mut idt.vmm_communication_exception, 29);
+        set_exception_stub(&mut idt.security_exception, 30);
         idt[IRQ_TIMER as u8]
             .set_handler_fn(timer_handler)
             .disable_interrupts(true);
+        for (offset, handler) in EXTERNAL_IRQ_HANDLERS.into_iter().enumerate() {
+            idt[IRQ_EXTERNAL_BASE + offset as u8]
+                .set_handler_fn(handler)
+                .disable_interrupts(true);
+        }
 
         idt
     };
 }
 
-pub fn init_idt() {
-    IDT.load();
+/// Points `entry` at the register-saving stub in `exception`.
+fn set_exception_stub<F>(entry: &mut Entry<F>, vector: u8) -> &mut EntryOptions {
+    let stub = exception::stub(vector).expect("no stub for this exception");
+    let options = unsafe { entry.set_handler_addr(VirtAddr::new(stub)) };
+    options.disable_interrupts(true);
+    options
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    let context = InterruptContext {
-        vector: 3,
-        instruction_pointer: stack_frame.instruction_pointer.as_u64(),
-        code_segment: stack_frame.code_segment.0 as u64,
-        cpu_flags: stack_frame.cpu_flags.bits(),
-        stack_pointer: stack_frame.stack_pointer.as_u64(),
-        stack_segment: stack_frame.stack_segment.0 as u64,
-    };
-
-    crate::interrupt::subscriber::dispatch_to_subscribers(&context);
-
-    warn!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) -> ! {
-    let context = InterruptContext {
-        vector: 8,
-        instruction_pointer: stack_frame.instruction_pointer.as_u64(),
-        code_segment: stack_frame.code_segment.0 as u64,
-        cpu_flags: stack_frame.cpu_flags.bits(),
-        stack_pointer: stack_frame.stack_pointer.as_u64(),
-        stack_segment: stack_frame.stack_segment.0 as u64,
-    };
-
-    crate::interrupt::subscriber::dispatch_to_subscribers(&context);
-
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+pub fn init_idt() {
+    IDT.load();
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: PageFaultErrorCode,
-) {
-    use x86_64::registers::control::Cr2;
-
+extern "x86-interrupt" fn timer_handler(stack_frame: InterruptStackFrame) {
     let context = InterruptContext {
-        vector: 14,
+        vector: IRQ_TIMER as u8,
         instruction_pointer: stack_frame.instruction_pointer.as_u64(),
         code_segment: stack_frame.code_segment.0 as u64,
         cpu_flags: stack_frame.cpu_flags.bits(),
@@ -88,18 +95,25 @@ extern "x86-interrupt" fn page_fault_handler(
         stack_segment: stack_frame.stack_segment.0 as u64,
     };
 
-    crate::interrupt::subscriber::dispatch_to_subscribers(&context);
-
-    panic!(
-        "EXCEPTION: PAGE FAULT\n{:#?}\nAccessed address: {:#x}",
-        stack_frame,
-        Cr2::read().unwrap().as_u64()
-    );
+    // Every CPU's timer fires at the same rate; only the BSP drives the clock
+    // and the timer subscribers.
+    match percpu::current() {
+        Some(cpu) if !cpu.is_bsp() => cpu.tick(),
+        cpu => {
+            if let Some(cpu) = cpu {
+                cpu.tick();
+            }
+            time::tick();
+            time::timer::run_expired();
+            crate::interrupt::subscriber::dispatch_to_subscribers(&context);
+        }
+    }
+    LAPIC.get().unwrap().write(EOI, 0);
 }
 
-extern "x86-interrupt" fn timer_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn external_irq_handler<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
     let context = InterruptContext {
-        vector: IRQ_TIMER as u8,
+        vector: VECTOR,
         instruction_pointer: stack_frame.instruction_pointer.as_u64(),
         code_segment: stack_frame.code_segment.0 as u64,
         cpu_flags: stack_frame.cpu_flags.bits(),
@@ -107,8 +121,8 @@ extern "x86-interrupt" fn timer_handler(stack_frame: InterruptStackFrame) {
         stack_segment: stack_frame.stack_segment.0 as u64,
     };
 
+    // Level-triggered sources must be quiesced by a subscriber before the EOI.
     crate::interrupt::subscriber::dispatch_to_subscribers(&context);
 
-    time::tick();
     LAPIC.get().unwrap().write(EOI, 0);
 }
@@ -0,0 +1,236 @@
+use acpi::{platform::interrupt, InterruptModel, PlatformInfo};
+use alloc::{alloc::Global, vec::Vec};
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+
+use crate::{
+    info,
+    interrupt::idt::{IRQ_EXTERNAL_BASE, IRQ_EXTERNAL_COUNT},
+};
+
+static IOAPICS: Once<IoApics> = Once::new();
+
+const IOREGSEL: usize = 0;
+const IOWIN: usize = 0x10 / 4;
+
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+const ACTIVE_LOW: u32 = 1 << 13;
+const LEVEL: u32 = 1 << 15;
+const MASKED: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// Where an ISA IRQ lands after the MADT interrupt source overrides.
+#[derive(Debug, Clone, Copy)]
+pub struct IsaRoute {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+struct IoApic {
+    ptr: *mut u32,
+    id: u8,
+    gsi_base: u32,
+    entries: u32,
+}
+
+unsafe impl Send for IoApic {}
+
+impl IoApic {
+    fn new(info: &interrupt::IoApic) -> Self {
+        let mut io_apic = IoApic {
+            ptr: info.address as u64 as *mut u32,
+            id: info.id,
+            gsi_base: info.global_system_interrupt_base,
+            entries: 0,
+        };
+        io_apic.entries = ((io_apic.read(IOAPICVER) >> 16) & 0xff) + 1;
+
+        io_apic
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        unsafe {
+            self.ptr.add(IOREGSEL).write_volatile(register);
+            self.ptr.add(IOWIN).read_volatile()
+        }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            self.ptr.add(IOREGSEL).write_volatile(register);
+            self.ptr.add(IOWIN).write_volatile(value);
+        }
+    }
+
+    fn contains(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_entry(&mut self, gsi: u32) -> (u32, u32) {
+        let register = IOREDTBL + (gsi - self.gsi_base) * 2;
+        (self.read(register), self.read(register + 1))
+    }
+
+    fn write_entry(&mut self, gsi: u32, low: u32, high: u32) {
+        let register = IOREDTBL + (gsi - self.gsi_base) * 2;
+        // Keep the entry masked while the two halves disagree.
+        self.write(register, MASKED);
+        self.write(register + 1, high);
+        self.write(register, low);
+    }
+}
+
+struct IoApics {
+    io_apics: Vec<Mutex<IoApic>>,
+    overrides: Vec<interrupt::InterruptSourceOverride>,
+}
+
+impl IoApics {
+    fn with_gsi<R>(&self, gsi: u32, f: impl FnOnce(&mut IoApic) -> R) -> Result<R, &'static str> {
+        interrupts::without_interrupts(|| {
+            for io_apic in &self.io_apics {
+                let mut io_apic = io_apic.lock();
+                if io_apic.contains(gsi) {
+                    return Ok(f(&mut io_apic));
+                }
+            }
+
+            Err("No IOAPIC handles this GSI")
+        })
+    }
+}
+
+/// Finds the IOAPICs in the MADT and masks every input. Call after the local
+/// APIC is up.
+pub fn init(platform_info: &PlatformInfo<'_, Global>) {
+    let apic_info = match platform_info.interrupt_model {
+        InterruptModel::Apic(ref apic) => apic,
+        _ => panic!("APIC not found in ACPI tables"),
+    };
+
+    let mut io_apics = Vec::new();
+    for info in apic_info.io_apics.iter() {
+        let mut io_apic = IoApic::new(info);
+        for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entries {
+            io_apic.write_entry(gsi, MASKED, 0);
+        }
+        info!(
+            "IOAPIC {}: GSI {}-{} at {:#x}",
+            io_apic.id,
+            io_apic.gsi_base,
+            io_apic.gsi_base + io_apic.entries - 1,
+            info.address
+        );
+        io_apics.push(Mutex::new(io_apic));
+    }
+
+    let overrides = apic_info
+        .interrupt_source_overrides
+        .iter()
+        .copied()
+        .collect();
+
+    IOAPICS.call_once(|| IoApics {
+        io_apics,
+        overrides,
+    });
+}
+
+/// Applies the MADT overrides to an ISA IRQ; without one the IRQ is an
+/// edge-triggered, active-high GSI of the same number.
+pub fn isa_route(irq: u8) -> IsaRoute {
+    let overridden = IOAPICS
+        .get()
+        .and_then(|ioapics| ioapics.overrides.iter().find(|o| o.isa_source == irq));
+
+    match overridden {
+        Some(o) => IsaRoute {
+            gsi: o.global_system_interrupt,
+            polarity: match o.polarity {
+                interrupt::Polarity::ActiveLow => Polarity::ActiveLow,
+                _ => Polarity::ActiveHigh,
+            },
+            trigger_mode: match o.trigger_mode {
+                interrupt::TriggerMode::Level => TriggerMode::Level,
+                _ => TriggerMode::Edge,
+            },
+        },
+        None => IsaRoute {
+            gsi: irq as u32,
+            polarity: Polarity::ActiveHigh,
+            trigger_mode: TriggerMode::Edge,
+        },
+    }
+}
+
+/// Delivers `gsi` as `vector` to the local APIC `apic_id`. The entry is left
+/// masked; `unmask` it once a subscriber for `vector` is in place.
+pub fn route(
+    gsi: u32,
+    vector: u8,
+    apic_id: u32,
+    polarity: Polarity,
+    trigger_mode: TriggerMode,
+) -> Result<(), &'static str> {
+    if !(IRQ_EXTERNAL_BASE..IRQ_EXTERNAL_BASE + IRQ_EXTERNAL_COUNT).contains(&vector) {
+        return Err("Vector is outside the external interrupt range");
+    }
+    if apic_id > 0xff {
+        return Err("IOAPIC cannot target an x2APIC ID");
+    }
+
+    let mut low = MASKED | vector as u32;
+    if polarity == Polarity::ActiveLow {
+        low |= ACTIVE_LOW;
+    }
+    if trigger_mode == TriggerMode::Level {
+        low |= LEVEL;
+    }
+
+    IOAPICS
+        .get()
+        .ok_or("IOAPIC is not initialized")?
+        .with_gsi(gsi, |io_apic| io_apic.write_entry(gsi, low, apic_id << 24))
+}
+
+/// `route` for a legacy ISA IRQ such as 4 (COM1) or 1 (PS/2 keyboard).
+/// Returns the GSI to pass to `mask`/`unmask`.
+pub fn route_isa(irq: u8, vector: u8, apic_id: u32) -> Result<u32, &'static str> {
+    let isa = isa_route(irq);
+    route(isa.gsi, vector, apic_id, isa.polarity, isa.trigger_mode)?;
+
+    Ok(isa.gsi)
+}
+
+pub fn mask(gsi: u32) -> Result<(), &'static str> {
+    set_masked(gsi, true)
+}
+
+pub fn unmask(gsi: u32) -> Result<(), &'static str> {
+    set_masked(gsi, false)
+}
+
+fn set_masked(gsi: u32, masked: bool) -> Result<(), &'static str> {
+    IOAPICS
+        .get()
+        .ok_or("IOAPIC is not initialized")?
+        .with_gsi(gsi, |io_apic| {
+            let (low, high) = io_apic.read_entry(gsi);
+            let low = if masked { low | MASKED } else { low & !MASKED };
+            io_apic.write_entry(gsi, low, high);
+        })
+}
@@ -1,4 +1,6 @@
 pub mod apic;
+pub mod exception;
 pub mod gdt;
 pub mod idt;
+pub mod ioapic;
 pub mod subscriber;
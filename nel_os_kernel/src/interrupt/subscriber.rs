@@ -1,4 +1,18 @@
-use spin::Mutex;
+//! Interrupt subscriptions keyed by vector.
+//!
+//! A callback owns its state (typically an `Arc`), so nothing in the table
+//! can outlive what it points at. Callbacks run in interrupt context with
+//! interrupts disabled and the table read-locked: they must not allocate,
+//! block, or subscribe/unsubscribe.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::RwLock;
+use x86_64::instructions::interrupts;
 
 #[derive(Debug)]
 pub struct InterruptContext {
@@ -10,57 +24,95 @@ pub struct InterruptContext {
     pub stack_segment: u64,
 }
 
-pub type SubscriberCallback = fn(*mut core::ffi::c_void, &InterruptContext);
+type Callback = Box<dyn Fn(&InterruptContext) + Send + Sync>;
 
-#[derive(Debug, Clone, Copy)]
-pub struct Subscriber {
-    pub callback: SubscriberCallback,
-    pub context: *mut core::ffi::c_void,
+struct Subscriber {
+    id: u64,
+    vectors: RangeInclusive<u8>,
+    callback: Callback,
 }
 
-unsafe impl Send for Subscriber {}
-unsafe impl Sync for Subscriber {}
+static SUBSCRIBERS: RwLock<Vec<Subscriber>> = RwLock::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
 
-const MAX_SUBSCRIBERS: usize = 10;
+/// One vector or an inclusive range of them.
+#[derive(Debug, Clone)]
+pub struct Vectors(RangeInclusive<u8>);
 
-static SUBSCRIBERS: Mutex<[Option<Subscriber>; MAX_SUBSCRIBERS]> =
-    Mutex::new([None; MAX_SUBSCRIBERS]);
+impl From<u8> for Vectors {
+    fn from(vector: u8) -> Self {
+        Vectors(vector..=vector)
+    }
+}
 
-pub fn subscribe(
-    callback: SubscriberCallback,
-    context: *mut core::ffi::c_void,
-) -> Result<(), &'static str> {
-    let mut subscribers = SUBSCRIBERS.lock();
-
-    for slot in subscribers.iter_mut() {
-        if slot.is_none() {
-            *slot = Some(Subscriber { callback, context });
-            return Ok(());
-        }
+impl From<RangeInclusive<u8>> for Vectors {
+    fn from(vectors: RangeInclusive<u8>) -> Self {
+        Vectors(vectors)
     }
+}
 
-    Err("No available subscriber slots")
+/// Keeps a callback registered; dropping it unsubscribes.
+#[must_use = "dropping a Subscription unsubscribes immediately"]
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
 }
 
-pub fn unsubscribe(callback: SubscriberCallback) -> Result<(), &'static str> {
-    let mut subscribers = SUBSCRIBERS.lock();
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let removed = interrupts::without_interrupts(|| {
+            let mut subscribers = SUBSCRIBERS.write();
+            subscribers
+                .iter()
+                .position(|subscriber| subscriber.id == self.id)
+                .map(|index| subscribers.remove(index))
+        });
+        // The callback and its captured state are freed here, outside the lock.
+        drop(removed);
+    }
+}
 
-    for slot in subscribers.iter_mut() {
-        if let Some(subscriber) = slot {
-            if core::ptr::fn_addr_eq(subscriber.callback, callback) {
-                *slot = None;
-                return Ok(());
-            }
-        }
+/// Calls `callback` for every interrupt on `vectors`, on whichever CPU takes it.
+/// Exceptions that arrive while the list is being changed skip their
+/// subscribers (see `try_dispatch_to_subscribers`).
+pub fn subscribe(
+    vectors: impl Into<Vectors>,
+    callback: impl Fn(&InterruptContext) + Send + Sync + 'static,
+) -> Result<Subscription, &'static str> {
+    let Vectors(vectors) = vectors.into();
+    if vectors.is_empty() {
+        return Err("Empty vector range");
     }
 
-    Err("Subscriber not found")
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let subscriber = Subscriber {
+        id,
+        vectors,
+        callback: Box::new(callback),
+    };
+
+    interrupts::without_interrupts(|| SUBSCRIBERS.write().push(subscriber));
+
+    Ok(Subscription { id })
 }
 
 pub fn dispatch_to_subscribers(context: &InterruptContext) {
-    let subscribers = SUBSCRIBERS.lock();
+    dispatch(&SUBSCRIBERS.read(), context);
+}
 
-    for subscriber in subscribers.iter().flatten() {
-        (subscriber.callback)(subscriber.context, context);
+/// Like `dispatch_to_subscribers`, but skips dispatch if the list is being
+/// changed: an exception may have been raised by `subscribe` itself, with the
+/// write lock held on this CPU.
+pub fn try_dispatch_to_subscribers(context: &InterruptContext) {
+    if let Some(subscribers) = SUBSCRIBERS.try_read() {
+        dispatch(&subscribers, context);
+    }
+}
+
+fn dispatch(subscribers: &[Subscriber], context: &InterruptContext) {
+    for subscriber in subscribers {
+        if subscriber.vectors.contains(&context.vector) {
+            (subscriber.callback)(context);
+        }
     }
 }
@@ -0,0 +1,58 @@
+//! Lock-free ring of the most recent log lines.
+//!
+//! Writers reserve space with a single `fetch_add` and never wait, so any
+//! context can log. A writer that is lapped while still copying, or a reader
+//! racing a writer, can see mixed bytes; the ring is for post-mortem context,
+//! not an exact record.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use crate::serial;
+
+pub const DMESG_SIZE: usize = 64 * 1024;
+
+static BUFFER: [AtomicU8; DMESG_SIZE] = [const { AtomicU8::new(0) }; DMESG_SIZE];
+/// Bytes ever written; the next write starts at `HEAD % DMESG_SIZE`.
+static HEAD: AtomicUsize = AtomicUsize::new(0);
+
+pub fn write(bytes: &[u8]) {
+    let bytes = &bytes[bytes.len().saturating_sub(DMESG_SIZE)..];
+    let start = HEAD.fetch_add(bytes.len(), Ordering::Relaxed);
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        BUFFER[(start + i) % DMESG_SIZE].store(byte, Ordering::Relaxed);
+    }
+}
+
+/// Calls `f` with the retained lines, oldest first, in chunks that do not
+/// necessarily end on a line boundary.
+pub fn read(mut f: impl FnMut(&[u8])) {
+    let head = HEAD.load(Ordering::Relaxed);
+    let mut position = head.saturating_sub(DMESG_SIZE);
+    let lapped = position > 0;
+
+    // The oldest line was partly overwritten; start at the next one.
+    if lapped {
+        while position < head && BUFFER[position % DMESG_SIZE].load(Ordering::Relaxed) != b'\n' {
+            position += 1;
+        }
+        position += 1;
+    }
+
+    let mut chunk = [0u8; 256];
+    while position < head {
+        let len = (head - position).min(chunk.len());
+        for (i, byte) in chunk[..len].iter_mut().enumerate() {
+            *byte = BUFFER[(position + i) % DMESG_SIZE].load(Ordering::Relaxed);
+        }
+        f(&chunk[..len]);
+        position += len;
+    }
+}
+
+/// Writes the ring to the serial port, bypassing the logger.
+pub fn dump() {
+    serial::write_bytes(b"---- dmesg ----\n");
+    read(serial::write_bytes);
+    serial::write_bytes(b"---- end of dmesg ----\n");
+}
@@ -0,0 +1,228 @@
+//! `log` backend. Every record that passes the per-module filters goes to
+//! the dmesg ring; those at or above the console level are also printed.
+//!
+//! `log::max_level` tracks the most verbose filter, so a disabled `trace!`
+//! costs one relaxed load and a compare, and its arguments are never
+//! formatted.
+
+pub mod dmesg;
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::RwLock;
+use x86_64::instructions::interrupts;
+
+use crate::time;
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => (::log::error!($($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => (::log::warn!($($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => (::log::info!($($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => (::log::debug!($($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => (::log::trace!($($arg)*));
+}
+
+/// Longest dmesg line; longer records are truncated in the ring only.
+const LINE_SIZE: usize = 512;
+const CRATE_PREFIX: &str = concat!(env!("CARGO_CRATE_NAME"), "::");
+
+struct Filter {
+    /// Module path without the crate name, e.g. `vmm::x86_64::intel`.
+    module: String,
+    level: LevelFilter,
+}
+
+static FILTERS: RwLock<Vec<Filter>> = RwLock::new(Vec::new());
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// Installs the logger. Records logged before this are dropped.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        update_max_level();
+    }
+}
+
+fn level_filter(value: usize) -> LevelFilter {
+    LevelFilter::iter().nth(value).unwrap_or(LevelFilter::Trace)
+}
+
+fn is_in_module(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+fn level_for(target: &str) -> LevelFilter {
+    let default = level_filter(DEFAULT_LEVEL.load(Ordering::Relaxed));
+    let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+
+    // A writer on this CPU may have been interrupted by an NMI or exception;
+    // fall back rather than spin on the lock.
+    let Some(filters) = FILTERS.try_read() else {
+        return default;
+    };
+    filters
+        .iter()
+        .filter(|filter| is_in_module(target, &filter.module))
+        .max_by_key(|filter| filter.module.len())
+        .map_or(default, |filter| filter.level)
+}
+
+fn update_max_level() {
+    let default = level_filter(DEFAULT_LEVEL.load(Ordering::Relaxed));
+    let max = FILTERS
+        .read()
+        .iter()
+        .map(|filter| filter.level)
+        .fold(default, Ord::max);
+    log::set_max_level(max);
+}
+
+pub fn set_default_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as usize, Ordering::Relaxed);
+    update_max_level();
+}
+
+/// Sets the level of `module` and everything below it, overriding the
+/// default and any filter on an enclosing module.
+pub fn set_level(module: &str, level: LevelFilter) {
+    let module = module.strip_prefix(CRATE_PREFIX).unwrap_or(module);
+
+    interrupts::without_interrupts(|| {
+        let mut filters = FILTERS.write();
+        match filters.iter_mut().find(|filter| filter.module == module) {
+            Some(filter) => filter.level = level,
+            None => filters.push(Filter {
+                module: String::from(module),
+                level,
+            }),
+        }
+    });
+    update_max_level();
+}
+
+/// Applies a filter list such as `info,vmm=trace,time=off`: a bare level
+/// sets the default, `module=level` sets one module.
+pub fn set_filters(spec: &str) -> Result<(), &'static str> {
+    for entry in spec.split(',').filter(|entry| !entry.is_empty()) {
+        match entry.split_once('=') {
+            Some((module, level)) => set_level(module, parse_level(level)?),
+            None => set_default_level(parse_level(entry)?),
+        }
+    }
+
+    Ok(())
+}
+
+/// Records above `level` still reach the dmesg ring.
+pub fn set_console_level(level: LevelFilter) {
+    CONSOLE_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Whether some recorded messages were kept off the console.
+pub fn is_console_filtered() -> bool {
+    level_filter(CONSOLE_LEVEL.load(Ordering::Relaxed)) < log::max_level()
+}
+
+pub fn parse_level(level: &str) -> Result<LevelFilter, &'static str> {
+    LevelFilter::from_str(level).map_err(|_| "Unknown log level")
+}
+
+fn level_letter(level: Level) -> char {
+    match level {
+        Level::Error => 'E',
+        Level::Warn => 'W',
+        Level::Info => 'I',
+        Level::Debug => 'D',
+        Level::Trace => 'T',
+    }
+}
+
+/// Formats into a fixed buffer, dropping what does not fit.
+struct LineBuffer {
+    bytes: [u8; LINE_SIZE],
+    len: usize,
+}
+
+impl LineBuffer {
+    fn as_bytes(&mut self) -> &[u8] {
+        if self.len == LINE_SIZE {
+            self.bytes[LINE_SIZE - 1] = b'\n';
+        }
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(LINE_SIZE - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let uptime = time::uptime().as_secs_f64();
+        let letter = level_letter(record.level());
+
+        let mut line = LineBuffer {
+            bytes: [0; LINE_SIZE],
+            len: 0,
+        };
+        let _ = writeln!(line, "[{uptime:>12.5} {letter}] {}", record.args());
+        let truncated = line.len == LINE_SIZE;
+        dmesg::write(line.as_bytes());
+
+        if record.level() <= level_filter(CONSOLE_LEVEL.load(Ordering::Relaxed)) {
+            // Only a line cut short in the buffer is formatted again, in full.
+            match core::str::from_utf8(line.as_bytes()) {
+                Ok(text) if !truncated => {
+                    crate::print!("{text}");
+                }
+                _ => {
+                    crate::print!("[{uptime:>12.5} {letter}] {}\n", record.args());
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
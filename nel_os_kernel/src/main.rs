@@ -6,56 +6,72 @@
 extern crate alloc;
 
 pub mod acpi;
+pub mod backtrace;
 pub mod constant;
 pub mod cpuid;
+pub mod crash;
 pub mod graphics;
 pub mod interrupt;
 pub mod logging;
+pub mod measurement;
 pub mod memory;
+pub mod module;
 pub mod platform;
 pub mod serial;
+pub mod smbios;
+pub mod smp;
 pub mod time;
 pub mod vmm;
 
 use core::arch::asm;
 use core::panic::PanicInfo;
-use core::ptr::addr_of;
 
 use ::acpi::AcpiTables;
-use spin::Once;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use nel_os_common::{
+    image::{KernelImage, KernelSegment},
+    memory::{MemoryRegion, MemoryRegionKind},
+    module::ModuleKind,
+    BootInfoFlags,
+};
+use spin::{Mutex, Once};
 use x86_64::{registers::control::Cr3, structures::paging::OffsetPageTable, VirtAddr};
 
 use crate::{
     acpi::KernelAcpiHandler,
-    constant::{KERNEL_STACK_SIZE, PKG_VERSION},
+    constant::{DEFAULT_GUEST_MEMORY_SIZE, KERNEL_STACK_SIZE, PHYSICAL_MEMORY_OFFSET, PKG_VERSION},
     graphics::{FrameBuffer, FRAME_BUFFER},
-    interrupt::apic,
-    memory::{allocator, bitmap::BitmapMemoryTable, paging},
+    interrupt::{apic, ioapic},
+    memory::{
+        allocator,
+        bitmap::{BitmapMemoryTable, GlobalFrameAllocator, FRAME_ALLOCATOR},
+        paging,
+        stack::GuardedStack,
+    },
 };
 
-pub static BZIMAGE_ADDR: Once<u64> = Once::new();
-pub static BZIMAGE_SIZE: Once<u64> = Once::new();
-pub static ROOTFS_ADDR: Once<u64> = Once::new();
-pub static ROOTFS_SIZE: Once<u64> = Once::new();
-
-#[repr(C, align(16))]
-struct AlignedStack {
-    stack: [u8; KERNEL_STACK_SIZE],
-}
+pub static MEMORY_MAP: Once<Vec<MemoryRegion>> = Once::new();
+pub static KERNEL_SEGMENTS: Once<Vec<KernelSegment>> = Once::new();
+pub static GUEST_CMDLINE: Once<String> = Once::new();
+pub static GUEST_PROFILE: Once<String> = Once::new();
+pub static GUEST_VM_CONF: Once<String> = Once::new();
+pub static GUEST_MEMORY_SIZE: Once<u64> = Once::new();
 
 #[used]
-static mut KERNEL_STACK: AlignedStack = AlignedStack {
-    stack: [0; KERNEL_STACK_SIZE],
-};
+static mut KERNEL_STACK: GuardedStack<KERNEL_STACK_SIZE> = GuardedStack::new();
 
 #[unsafe(no_mangle)]
 pub extern "sysv64" fn asm_main() -> ! {
     unsafe {
-        let stack_base = addr_of!(KERNEL_STACK.stack) as *const u8;
-        let stack_top = stack_base.add(KERNEL_STACK_SIZE);
+        let stack_top = GuardedStack::top(&raw const KERNEL_STACK);
 
         asm!(
             "mov rsp, {stack_top}",
+            // Terminates frame-pointer backtraces.
+            "xor ebp, ebp",
             "call {main}",
             stack_top = in(reg) stack_top,
             main = sym main
@@ -67,7 +83,13 @@ pub extern "sysv64" fn asm_main() -> ! {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Before printing, which can itself fault or deadlock.
+    crash::record_panic(info);
+    if logging::is_console_filtered() {
+        logging::dmesg::dump();
+    }
     println!("{}", info);
+    backtrace::print_current();
     hlt_loop();
 }
 
@@ -80,12 +102,70 @@ fn hlt_loop() -> ! {
     }
 }
 
+/// `log=<filters>` (see `logging::set_filters`) and `log_console=<level>`.
+fn apply_log_options(cmdline: &str) {
+    for option in cmdline.split_whitespace() {
+        let result = if let Some(spec) = option.strip_prefix("log=") {
+            logging::set_filters(spec)
+        } else if let Some(level) = option.strip_prefix("log_console=") {
+            logging::parse_level(level).map(logging::set_console_level)
+        } else {
+            continue;
+        };
+
+        if let Err(e) = result {
+            warn!("Ignoring {}: {}", option, e);
+        }
+    }
+}
+
+fn log_memory_map(regions: &[MemoryRegion]) {
+    const KINDS: [(MemoryRegionKind, &str); 11] = [
+        (MemoryRegionKind::Usable, "Usable"),
+        (MemoryRegionKind::Bootloader, "Bootloader"),
+        (MemoryRegionKind::AcpiReclaim, "ACPI reclaim"),
+        (MemoryRegionKind::AcpiNvs, "ACPI NVS"),
+        (MemoryRegionKind::RuntimeServicesCode, "Runtime code"),
+        (MemoryRegionKind::RuntimeServicesData, "Runtime data"),
+        (MemoryRegionKind::Mmio, "MMIO"),
+        (MemoryRegionKind::Reserved, "Reserved"),
+        (MemoryRegionKind::Unusable, "Unusable"),
+        (MemoryRegionKind::Persistent, "Persistent"),
+        (MemoryRegionKind::CrashLog, "Crash log"),
+    ];
+
+    info!("Memory map: {} regions", regions.len());
+    for (kind, name) in KINDS {
+        let (count, size) = regions
+            .iter()
+            .filter(|region| region.kind() == kind)
+            .fold((0, 0), |(count, size), region| {
+                (count + 1, size + region.size())
+            });
+        if count > 0 {
+            info!("  {:<12}: {:>4} regions, {}KiB", name, count, size / 1024);
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "sysv64" fn main(boot_info: &nel_os_common::BootInfo) {
+    logging::init();
     serial::disable_screen_output();
 
+    if let Err(e) = boot_info.validate() {
+        error!("Incompatible boot info: {}", e);
+        error!("The bootloader and kernel must be built from the same nel_os_common");
+        hlt_loop();
+    }
+
     interrupt::gdt::init();
     interrupt::idt::init_idt();
+    if let Some(image) = boot_info.kernel_image() {
+        // The bootloader's memory, including this description, is never reused.
+        backtrace::init(unsafe { &*(image as *const KernelImage) });
+    }
+    crash::init(boot_info);
 
     let virt = VirtAddr::new(
         x86_64::registers::control::Cr3::read()
@@ -106,34 +186,59 @@ pub extern "sysv64" fn main(boot_info: &nel_os_common::BootInfo) {
     info!("Usable memory: {}MiB", count / 1024 / 1024);
     memory::bitmap::MAX_MEMORY.call_once(|| max_range as usize * 2);
 
-    let mut bitmap_table = BitmapMemoryTable::init(&boot_info.usable_memory);
+    let mut bitmap_table =
+        BitmapMemoryTable::init(&boot_info.usable_memory, boot_info.memory_map());
+    if let Some(image) = boot_info.kernel_image() {
+        bitmap_table.reserve_range(image.start, image.end);
+    }
+    smp::reserve_trampoline(&mut bitmap_table);
     info!(
         "Memory bitmap initialized: {} -> {}",
         bitmap_table.start, bitmap_table.end
     );
 
-    let mut usable_frame = 0;
-    for i in bitmap_table.start..bitmap_table.end {
-        if bitmap_table.get_bit(i) {
-            usable_frame += 1;
-        }
-    }
+    let usable_frame = bitmap_table.free_count();
 
     info!("Usable memory in bitmap: {}MiB", usable_frame * 4 / 1024);
 
     let mut mapper = {
-        let lv4_table_ptr = paging::init_page_table(&mut bitmap_table);
+        let kernel_segments = boot_info
+            .kernel_image()
+            .map(|image| image.segments())
+            .unwrap_or(&[]);
+        let [double_fault_guard, nmi_guard, machine_check_guard] =
+            interrupt::gdt::ist_stack_guards();
+        let guard_pages = [
+            GuardedStack::guard_page(&raw const KERNEL_STACK),
+            double_fault_guard,
+            nmi_guard,
+            machine_check_guard,
+            vmm::x86_64::intel::vcpu::vmexit_stack_guard(),
+        ];
+        let lv4_table_ptr =
+            paging::init_page_table(&mut bitmap_table, kernel_segments, &guard_pages);
         let lv4_table = unsafe { &mut *lv4_table_ptr };
-        unsafe { OffsetPageTable::new(lv4_table, VirtAddr::new(0x0)) }
+        unsafe { OffsetPageTable::new(lv4_table, VirtAddr::new(PHYSICAL_MEMORY_OFFSET)) }
     };
 
     info!("Page table initialized");
 
     allocator::init_heap(&mut mapper, &mut bitmap_table).unwrap();
+    let (bitmap_start, bitmap_end) = (bitmap_table.start, bitmap_table.end);
+    FRAME_ALLOCATOR.call_once(|| Mutex::new(bitmap_table));
+    let mut frame_allocator = GlobalFrameAllocator;
+
+    MEMORY_MAP.call_once(|| {
+        let mut regions = boot_info.memory_map().to_vec();
+        regions.sort_unstable_by_key(|region| region.start);
+        regions
+    });
+    if let Some(image) = boot_info.kernel_image() {
+        KERNEL_SEGMENTS.call_once(|| image.segments().to_vec());
+    }
 
-    if boot_info.frame_buffer.is_some() {
-        let frame_buffer =
-            FrameBuffer::from_raw_buffer(boot_info.frame_buffer.as_ref().unwrap(), (64, 64, 64));
+    if let Some(raw_frame_buffer) = boot_info.frame_buffer() {
+        let frame_buffer = FrameBuffer::from_raw_buffer(raw_frame_buffer, (64, 64, 64));
         frame_buffer.clear();
 
         FRAME_BUFFER.lock().replace(frame_buffer);
@@ -143,50 +248,129 @@ pub extern "sysv64" fn main(boot_info: &nel_os_common::BootInfo) {
 
     println!("");
     info!("Kernel initialized successfully");
+    crash::report_previous();
 
     info!("Kernel version: {}", PKG_VERSION);
     info!(
-        "Level 4 page table at {:#x}",
-        Cr3::read().0.start_address().as_u64()
+        "Boot info: version {}, flags {:#x}",
+        boot_info.version,
+        boot_info.flags.bits()
     );
+    info!("Hypervisor options: {}", boot_info.cmdline());
+    apply_log_options(boot_info.cmdline());
+    if let Some(image) = boot_info.kernel_image() {
+        info!(
+            "Kernel image: {:#x} -> {:#x} (load bias {:#x})",
+            image.start, image.end, image.load_bias
+        );
+        for segment in image.segments() {
+            info!(
+                "  {:#x} -> {:#x} {}",
+                segment.start, segment.end, segment.flags
+            );
+        }
+    }
     info!(
-        "Memory bitmap: {} -> {}",
-        bitmap_table.start, bitmap_table.end
+        "Level 4 page table at {:#x}",
+        Cr3::read().0.start_address().as_u64()
     );
+    info!("Memory bitmap: {} -> {}", bitmap_start, bitmap_end);
     info!("CPU: {} {}", cpuid::get_vendor_id(), cpuid::get_brand());
+    log_memory_map(MEMORY_MAP.get().unwrap());
     info!(
         "Usable memory: {}MiB ({:.1}GiB)",
         usable_frame * 4 / 1024,
         usable_frame as f64 * 4. / 1024. / 1024.
     );
 
-    if let Some(rsdp) = boot_info.rsdp {
-        info!("RSDP: {:#x}", rsdp);
+    if let Some(system_table) = boot_info.efi_system_table() {
+        // EFI_TABLE_HEADER.Revision, then FirmwareRevision after the vendor pointer.
+        let revision = unsafe { core::ptr::read_unaligned((system_table + 8) as *const u32) };
+        let firmware_revision =
+            unsafe { core::ptr::read_unaligned((system_table + 32) as *const u32) };
+        info!(
+            "EFI system table: {:#x}, UEFI {}.{}, firmware revision {:#x}",
+            system_table,
+            revision >> 16,
+            (revision & 0xffff) / 10,
+            firmware_revision
+        );
+    }
+    smbios::init(boot_info.smbios(), boot_info.smbios3());
+
+    if let Some(rsdp) = boot_info.rsdp() {
+        let rsdp_table = unsafe { &*(rsdp as *const ::acpi::rsdp::Rsdp) };
+        if rsdp_table.revision() >= 2 {
+            info!(
+                "RSDP: {:#x} (ACPI 2.0+, OEM {}, XSDT {:#x})",
+                rsdp,
+                rsdp_table.oem_id(),
+                rsdp_table.xsdt_address()
+            );
+        } else {
+            info!("RSDP: {:#x} (ACPI 1.0, OEM {})", rsdp, rsdp_table.oem_id());
+        }
 
         let acpi_tables =
             unsafe { AcpiTables::from_rsdp(KernelAcpiHandler, rsdp as usize) }.unwrap();
         let platform_info = acpi_tables.platform_info().unwrap();
 
-        apic::init_local_apic(platform_info);
+        time::init(
+            ::acpi::HpetInfo::new(&acpi_tables).ok(),
+            platform_info.pm_timer.as_ref(),
+        );
+
+        apic::init_local_apic(&platform_info);
         info!("Local APIC initialized",);
+        ioapic::init(&platform_info);
+        smp::init_bsp();
 
         x86_64::instructions::interrupts::enable();
 
         info!("Interrupts enabled");
+
+        smp::start_aps(&platform_info);
     }
 
-    BZIMAGE_ADDR.call_once(|| boot_info.bzimage_addr);
-    BZIMAGE_SIZE.call_once(|| boot_info.bzimage_size);
-    ROOTFS_ADDR.call_once(|| boot_info.rootfs_addr);
-    ROOTFS_SIZE.call_once(|| boot_info.rootfs_size);
+    module::init(boot_info.modules());
+    measurement::init(
+        boot_info.measurements(),
+        boot_info.has(BootInfoFlags::VERIFIED),
+    );
 
-    let mut vcpu = vmm::get_vcpu(&mut bitmap_table).unwrap();
+    let guest = match boot_info.guest() {
+        Some(guest) if module::first_of_kind(ModuleKind::Kernel).is_some() => guest,
+        _ => {
+            warn!("No guest kernel was loaded, running without a guest");
+            hlt_loop();
+        }
+    };
 
+    let cmdline = guest.cmdline();
+    let guest_memory_size = match guest.memory_size {
+        0 => DEFAULT_GUEST_MEMORY_SIZE,
+        size => size,
+    };
+    GUEST_CMDLINE.call_once(|| cmdline.to_string());
+    GUEST_PROFILE.call_once(|| guest.profile().to_string());
+    GUEST_VM_CONF.call_once(|| guest.vm_conf().to_string());
+    GUEST_MEMORY_SIZE.call_once(|| guest_memory_size);
+    info!("Guest profile: {}", guest.profile());
+    info!("Guest cmdline: {}", cmdline);
+    info!("Guest memory: {}MiB", guest_memory_size / 1024 / 1024);
+
+    let mut vcpu = vmm::get_vcpu(&mut frame_allocator).unwrap();
+
+    info!("Heap: {}", allocator::stats());
+    for slab in memory::slab::stats().filter(|slab| slab.slabs > 0) {
+        info!("  Slab {}", slab);
+    }
     info!("Running guest VM...");
     loop {
-        let result = vcpu.run(&mut bitmap_table);
+        let result = vcpu.run(&mut frame_allocator);
         if let Err(e) = result {
             error!("VCPU run failed: {}", e);
+            error!("Heap: {}", allocator::stats());
             break;
         }
     }
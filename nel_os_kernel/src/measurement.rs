@@ -0,0 +1,55 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use nel_os_common::measurement::{HexDigest, Measurement};
+use spin::Once;
+
+use crate::{info, warn};
+
+static MEASUREMENTS: Once<(Vec<MeasuredImage>, bool)> = Once::new();
+
+pub struct MeasuredImage {
+    pub name: String,
+    pub sha256: [u8; 32],
+}
+
+/// Copies the bootloader's measurements onto the heap. Needs the allocator.
+pub fn init(measurements: &[Measurement], verified: bool) {
+    MEASUREMENTS.call_once(|| {
+        let images = measurements
+            .iter()
+            .map(|measurement| MeasuredImage {
+                name: measurement.name().to_string(),
+                sha256: measurement.sha256,
+            })
+            .collect();
+        (images, verified)
+    });
+
+    if !verified {
+        warn!("Boot images were not verified against a manifest");
+    }
+    for image in all() {
+        info!("sha256 {} {}", HexDigest(&image.sha256), image.name);
+    }
+}
+
+pub fn all() -> &'static [MeasuredImage] {
+    MEASUREMENTS
+        .get()
+        .map(|(images, _)| images.as_slice())
+        .unwrap_or(&[])
+}
+
+pub fn find(name: &str) -> Option<&'static MeasuredImage> {
+    all().iter().find(|image| image.name == name)
+}
+
+/// True when the bootloader checked every image against its manifest.
+pub fn verified() -> bool {
+    MEASUREMENTS
+        .get()
+        .map(|(_, verified)| *verified)
+        .unwrap_or(false)
+}
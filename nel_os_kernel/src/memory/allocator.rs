@@ -1,18 +1,109 @@
-use linked_list_allocator::LockedHeap;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    ptr::{self, NonNull},
+};
+
+use linked_list_allocator::Heap;
+use spin::Mutex;
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
 
 use crate::{
-    constant::{HEAP_SIZE, HEAP_START},
+    constant::{
+        HEAP_GROW_SIZE, HEAP_MAX_SIZE, HEAP_SIZE, HEAP_START, PAGE_SIZE, PHYSICAL_MEMORY_OFFSET,
+    },
     info,
+    memory::{bitmap::GlobalFrameAllocator, paging, slab},
 };
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: GrowableHeap = GrowableHeap::empty();
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub used: usize,
+    pub free: usize,
+    /// Highest `used` seen since boot.
+    pub peak: usize,
+    pub size: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}KiB used, {}KiB free, {}KiB peak, {}KiB mapped",
+            self.used / 1024,
+            self.free / 1024,
+            self.peak / 1024,
+            self.size / 1024
+        )
+    }
+}
+
+struct HeapState {
+    heap: Heap,
+    peak: usize,
+}
+
+/// Small objects come from the slab size classes; everything else (and all
+/// allocations before the frame allocator is up) from a linked-list heap that
+/// maps more frames after its top when an allocation does not fit, up to
+/// `HEAP_MAX_SIZE`.
+struct GrowableHeap(Mutex<HeapState>);
+
+impl GrowableHeap {
+    const fn empty() -> Self {
+        Self(Mutex::new(HeapState {
+            heap: Heap::empty(),
+            peak: 0,
+        }))
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(ptr) = slab::allocate(layout) {
+            return ptr.as_ptr();
+        }
+
+        let mut state = self.0.lock();
+
+        let ptr = match state.heap.allocate_first_fit(layout) {
+            Ok(ptr) => ptr,
+            Err(()) => {
+                if grow(&mut state.heap, layout).is_err() {
+                    return ptr::null_mut();
+                }
+                match state.heap.allocate_first_fit(layout) {
+                    Ok(ptr) => ptr,
+                    Err(()) => return ptr::null_mut(),
+                }
+            }
+        };
+
+        state.peak = state.peak.max(state.heap.used());
+        ptr.as_ptr()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(ptr) = NonNull::new(ptr) else {
+            return;
+        };
+
+        // Slabs live in identity-mapped frames, far below the heap's virtual range.
+        if (HEAP_START..HEAP_START + HEAP_MAX_SIZE).contains(&(ptr.as_ptr() as usize)) {
+            unsafe { self.0.lock().heap.deallocate(ptr, layout) };
+        } else {
+            unsafe { slab::deallocate(ptr, layout) };
+        }
+    }
+}
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -23,25 +114,70 @@ pub fn init_heap(
         HEAP_START,
         HEAP_START + HEAP_SIZE - 1
     );
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    for addr in (HEAP_START..HEAP_START + HEAP_SIZE).step_by(PAGE_SIZE) {
+        map_page(mapper, frame_allocator, addr)?;
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.0.lock().heap.init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+pub fn stats() -> HeapStats {
+    let state = ALLOCATOR.0.lock();
+
+    HeapStats {
+        used: state.heap.used(),
+        free: state.heap.free(),
+        peak: state.peak,
+        size: state.heap.size(),
+    }
+}
+
+/// Extends the heap by at least enough for `layout`, in `HEAP_GROW_SIZE` steps.
+fn grow(heap: &mut Heap, layout: Layout) -> Result<(), MapToError<Size4KiB>> {
+    let needed = (layout.size() + layout.align()).next_multiple_of(PAGE_SIZE);
+    let by = needed.max(HEAP_GROW_SIZE);
+    if heap.size() + by > HEAP_MAX_SIZE {
+        return Err(MapToError::FrameAllocationFailed);
+    }
+
+    let mut mapper = unsafe {
+        OffsetPageTable::new(
+            paging::get_active_level_4_table(),
+            VirtAddr::new(PHYSICAL_MEMORY_OFFSET),
+        )
+    };
+
+    // Whatever got mapped before a failure still joins the heap, so the next
+    // attempt starts from the new top instead of hitting mapped pages.
+    let top = heap.top();
+    let mut mapped = 0;
+    let result = (top..top + by).step_by(PAGE_SIZE).try_for_each(|addr| {
+        map_page(&mut mapper, &mut GlobalFrameAllocator, addr)?;
+        mapped += PAGE_SIZE;
+        Ok(())
+    });
+    if mapped > 0 {
+        unsafe { heap.extend(mapped) };
     }
 
+    result
+}
+
+fn map_page(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    addr: usize,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::containing_address(VirtAddr::new(addr as u64));
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+
     Ok(())
 }
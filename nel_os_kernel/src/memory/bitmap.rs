@@ -1,15 +1,18 @@
 use core::slice;
 
-use nel_os_common::memory::{self, UsableMemory};
-use spin::Once;
+use nel_os_common::memory::{self, MemoryRegion, MemoryRegionKind, UsableMemory};
+use spin::{Mutex, Once};
 use x86_64::{
-    structures::paging::{FrameAllocator, PhysFrame, Size4KiB},
+    instructions::interrupts,
+    structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame},
     PhysAddr,
 };
 
 use crate::constant::{BITS_PER_ENTRY, PAGE_SIZE};
 
 pub static MAX_MEMORY: Once<usize> = Once::new();
+/// The system frame table once early boot is done with it.
+pub static FRAME_ALLOCATOR: Once<Mutex<BitmapMemoryTable>> = Once::new();
 
 pub fn get_entry_count() -> usize {
     MAX_MEMORY.get().unwrap_or(&0) / PAGE_SIZE / BITS_PER_ENTRY
@@ -22,7 +25,7 @@ pub struct BitmapMemoryTable {
 }
 
 impl BitmapMemoryTable {
-    pub fn init(usable_memory: &UsableMemory) -> Self {
+    pub fn init(usable_memory: &UsableMemory, memory_map: &[MemoryRegion]) -> Self {
         let mut max_addr = 0u64;
         for range in usable_memory.ranges() {
             max_addr = max_addr.max(range.end);
@@ -52,6 +55,12 @@ impl BitmapMemoryTable {
             table.set_range(range);
         }
 
+        for region in memory_map {
+            if region.kind() != MemoryRegionKind::Usable {
+                table.reserve_range(region.start, region.end);
+            }
+        }
+
         let bitmap_start_frame = Self::addr_to_pfn(bitmap_addr);
         let bitmap_frames = bitmap_size.div_ceil(PAGE_SIZE);
         for i in 0..bitmap_frames {
@@ -62,7 +71,7 @@ impl BitmapMemoryTable {
             let index = entry_count - i - 1;
             if table.used_map[index] != 0 {
                 let offset = 63 - table.used_map[index].leading_zeros();
-                table.end = index * BITS_PER_ENTRY + (BITS_PER_ENTRY - offset as usize);
+                table.end = index * BITS_PER_ENTRY + offset as usize + 1;
                 break;
             }
         }
@@ -71,7 +80,103 @@ impl BitmapMemoryTable {
     }
 
     pub fn get_free_pfn(&self) -> Option<usize> {
-        (self.start..self.end).find(|&i| self.get_bit(i))
+        self.next_free(self.start)
+    }
+
+    /// First frame of `count` free frames starting at a multiple of `align` frames.
+    pub fn find_free_run(&self, count: usize, align: usize) -> Option<usize> {
+        self.find_free_run_from(self.start, count, align)
+    }
+
+    fn find_free_run_from(&self, from: usize, count: usize, align: usize) -> Option<usize> {
+        if count == 0 || !align.is_power_of_two() {
+            return None;
+        }
+
+        let mut frame = self.next_free(from)?.next_multiple_of(align);
+        while frame.checked_add(count)? <= self.end {
+            match self.next_used(frame, frame + count) {
+                None => return Some(frame),
+                Some(used) => frame = self.next_free(used + 1)?.next_multiple_of(align),
+            }
+        }
+
+        None
+    }
+
+    /// Physically contiguous frames for DMA buffers and large pages.
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysAddr> {
+        let frame = self.find_free_run(count, align)?;
+        self.set_frames(frame, count, false);
+
+        Some(PhysAddr::new(Self::pfn_to_addr(frame) as u64))
+    }
+
+    /// Like `allocate_contiguous`, for memory that must end below `limit`,
+    /// such as real-mode code. Never returns frame 0.
+    pub fn allocate_contiguous_below(
+        &mut self,
+        count: usize,
+        align: usize,
+        limit: u64,
+    ) -> Option<PhysAddr> {
+        let frame = self.find_free_run_from(self.start.max(1), count, align)?;
+        if Self::pfn_to_addr(frame + count) as u64 > limit {
+            return None;
+        }
+        self.set_frames(frame, count, false);
+
+        Some(PhysAddr::new(Self::pfn_to_addr(frame) as u64))
+    }
+
+    /// Returns frames from `allocate_contiguous` or `allocate_frame` to the pool.
+    pub fn free_contiguous(&mut self, addr: PhysAddr, count: usize) {
+        self.set_frames(Self::addr_to_pfn(addr.as_u64() as usize), count, true);
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.used_map
+            .iter()
+            .map(|entry| entry.count_ones() as usize)
+            .sum()
+    }
+
+    /// Lowest free frame at or after `from`, skipping fully used entries.
+    fn next_free(&self, from: usize) -> Option<usize> {
+        let mut frame = from;
+        while frame < self.end {
+            let index = Self::frame_to_index(frame);
+            let free = self.used_map[index] & (usize::MAX << Self::frame_to_offset(frame));
+            if free != 0 {
+                let found = index * BITS_PER_ENTRY + free.trailing_zeros() as usize;
+                return (found < self.end).then_some(found);
+            }
+            frame = (index + 1) * BITS_PER_ENTRY;
+        }
+
+        None
+    }
+
+    /// Lowest used frame in `start..end`, skipping fully free entries.
+    fn next_used(&self, start: usize, end: usize) -> Option<usize> {
+        let mut frame = start;
+        while frame < end {
+            let index = Self::frame_to_index(frame);
+            let used = !self.used_map[index] & (usize::MAX << Self::frame_to_offset(frame));
+            if used != 0 {
+                let found = index * BITS_PER_ENTRY + used.trailing_zeros() as usize;
+                return (found < end).then_some(found);
+            }
+            frame = (index + 1) * BITS_PER_ENTRY;
+        }
+
+        None
+    }
+
+    fn set_frames(&mut self, start: usize, count: usize, state: bool) {
+        for frame in start..start + count {
+            self.set_frame(frame, state);
+        }
     }
 
     pub fn set_range(&mut self, range: &memory::Range) {
@@ -83,6 +188,16 @@ impl BitmapMemoryTable {
         }
     }
 
+    pub fn reserve_range(&mut self, start: u64, end: u64) {
+        let max_frame = self.used_map.len() * BITS_PER_ENTRY;
+        let start = Self::addr_to_pfn(start as usize);
+        let end = Self::addr_to_pfn((end as usize).div_ceil(PAGE_SIZE) * PAGE_SIZE).min(max_frame);
+
+        for frame in start..end {
+            self.set_frame(frame, false);
+        }
+    }
+
     pub fn set_frame(&mut self, frame: usize, state: bool) {
         let index = Self::frame_to_index(frame);
         let offset = Self::frame_to_offset(frame);
@@ -122,16 +237,41 @@ impl BitmapMemoryTable {
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BitmapMemoryTable {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        if let Some(frame) = self.get_free_pfn() {
-            self.set_frame(frame, false);
-            Some(
-                PhysFrame::from_start_address(PhysAddr::new(Self::pfn_to_addr(frame) as u64))
-                    .unwrap(),
-            )
-        } else {
-            None
-        }
+/// 4KiB, 2MiB and 1GiB frames, each aligned to its own size.
+unsafe impl<S: PageSize> FrameAllocator<S> for BitmapMemoryTable {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        let frames = S::SIZE as usize / PAGE_SIZE;
+        let addr = self.allocate_contiguous(frames, frames)?;
+
+        Some(PhysFrame::from_start_address(addr).unwrap())
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BitmapMemoryTable {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        self.free_contiguous(frame.start_address(), S::SIZE as usize / PAGE_SIZE);
+    }
+}
+
+/// Handle to `FRAME_ALLOCATOR` that locks it per call, so callers such as the
+/// heap can allocate frames without holding the table for long.
+pub struct GlobalFrameAllocator;
+
+impl GlobalFrameAllocator {
+    pub fn with<R>(f: impl FnOnce(&mut BitmapMemoryTable) -> R) -> Option<R> {
+        let table = FRAME_ALLOCATOR.get()?;
+        Some(interrupts::without_interrupts(|| f(&mut table.lock())))
+    }
+}
+
+unsafe impl<S: PageSize> FrameAllocator<S> for GlobalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        Self::with(|table| table.allocate_frame())?
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for GlobalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        Self::with(|table| unsafe { table.deallocate_frame(frame) });
     }
 }
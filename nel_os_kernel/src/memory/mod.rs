@@ -1,3 +1,12 @@
+use x86_64::structures::paging::{FrameAllocator, Size2MiB, Size4KiB};
+
 pub mod allocator;
 pub mod bitmap;
 pub mod paging;
+pub mod slab;
+pub mod stack;
+
+/// A frame allocator that can also hand out 2MiB frames, usable behind `dyn`.
+pub trait LargeFrameAllocator: FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB> {}
+
+impl<T: FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB>> LargeFrameAllocator for T {}
@@ -1,15 +1,28 @@
+use nel_os_common::image::{KernelSegment, SegmentFlags};
 use x86_64::{
-    registers::control::{Cr3, Cr3Flags},
+    registers::{
+        control::{Cr0, Cr0Flags, Cr3, Cr3Flags},
+        model_specific::{Efer, EferFlags},
+    },
     structures::paging::{
-        page_table::FrameError, FrameAllocator, PageSize, PageTable, PageTableFlags, PhysFrame,
-        Size1GiB, Size4KiB,
+        page_table::{FrameError, PageTableEntry},
+        FrameAllocator, PageSize, PageTable, PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+        Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
 
 use crate::info;
 
-pub fn init_page_table(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> *mut PageTable {
+/// Builds the kernel address space: the first 512GiB of physical memory as an
+/// RW+NX window at `PHYSICAL_MEMORY_OFFSET` (identity), with the kernel image remapped at
+/// 4KiB granularity using its segment permissions (code RX, rodata R, data
+/// RW+NX) and the given stack guard pages left unmapped.
+pub fn init_page_table(
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    kernel_segments: &[KernelSegment],
+    guard_pages: &[u64],
+) -> *mut PageTable {
     let (lv4_frame, lv4_table) = new_page_table(frame_allocator);
     let (lv3_frame, lv3_table) = new_page_table(frame_allocator);
 
@@ -19,35 +32,130 @@ pub fn init_page_table(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> *
 
     lv4[0].set_frame(lv3_frame, base_flags);
 
-    unsafe {
-        let lv3: &mut PageTable = &mut *lv3_table;
+    let lv3: &mut PageTable = unsafe { &mut *lv3_table };
+    for (index, lv3_pte) in lv3.iter_mut().enumerate() {
+        lv3_pte.set_addr(
+            PhysAddr::new(index as u64 * Size1GiB::SIZE),
+            base_flags | PageTableFlags::HUGE_PAGE | PageTableFlags::NO_EXECUTE,
+        );
+    }
 
-        for (index, lv3_pte) in lv3.iter_mut().enumerate() {
-            lv3_pte.set_addr(
-                PhysAddr::new(index as u64 * Size1GiB::SIZE),
-                base_flags | PageTableFlags::HUGE_PAGE,
+    // Start every image page read-only and non-executable, then add what each
+    // overlapping segment needs, so pages shared by two segments get the union.
+    for segment in kernel_segments {
+        for addr in (segment.start & !0xfff..segment.end).step_by(Size4KiB::SIZE as usize) {
+            let entry = split_to_4k(lv3, addr, frame_allocator);
+            entry.set_flags(
+                PageTableFlags::PRESENT | PageTableFlags::ACCESSED | PageTableFlags::NO_EXECUTE,
             );
         }
     }
+    for segment in kernel_segments {
+        for addr in (segment.start & !0xfff..segment.end).step_by(Size4KiB::SIZE as usize) {
+            let entry = split_to_4k(lv3, addr, frame_allocator);
+            let mut flags = entry.flags();
+            if segment.flags.contains(SegmentFlags::WRITE) {
+                flags.insert(PageTableFlags::WRITABLE);
+            }
+            if segment.flags.contains(SegmentFlags::EXECUTE) {
+                flags.remove(PageTableFlags::NO_EXECUTE);
+            }
+            entry.set_flags(flags);
+        }
+    }
+
+    for &guard_page in guard_pages {
+        split_to_4k(lv3, guard_page, frame_allocator).set_unused();
+    }
 
+    info!(
+        "Kernel address space: {} segments, {} guard pages",
+        kernel_segments.len(),
+        guard_pages.len()
+    );
     info!("Setting new page table...");
 
     unsafe {
+        // NX bits are reserved until EFER.NXE is set.
+        Efer::update(|efer| efer.insert(EferFlags::NO_EXECUTE_ENABLE));
         Cr3::write(lv4_frame, Cr3Flags::empty());
+        // Read-only pages would otherwise only stop user-mode writes.
+        Cr0::update(|cr0| cr0.insert(Cr0Flags::WRITE_PROTECT));
     }
 
     lv4_table
 }
 
+/// Returns the 4KiB entry identity mapping `addr`, splitting the 1GiB and
+/// 2MiB pages above it while keeping their flags.
+fn split_to_4k<'a>(
+    lv3: &'a mut PageTable,
+    addr: u64,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> &'a mut PageTableEntry {
+    let addr = VirtAddr::new(addr);
+    let lv2 = split_huge_entry(&mut lv3[addr.p3_index()], Size2MiB::SIZE, frame_allocator);
+    let lv1 = split_huge_entry(&mut lv2[addr.p2_index()], Size4KiB::SIZE, frame_allocator);
+
+    &mut lv1[addr.p1_index()]
+}
+
+fn split_huge_entry<'a>(
+    entry: &mut PageTableEntry,
+    child_size: u64,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> &'a mut PageTable {
+    if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return frame_to_page_table(entry.frame().unwrap());
+    }
+
+    let (frame, table) = new_page_table(frame_allocator);
+    let table = unsafe { &mut *table };
+
+    let mut flags = entry.flags();
+    if child_size == Size4KiB::SIZE {
+        flags.remove(PageTableFlags::HUGE_PAGE);
+    }
+    for (index, child) in table.iter_mut().enumerate() {
+        child.set_addr(entry.addr() + index as u64 * child_size, flags);
+    }
+
+    // Permissions are enforced at the leaves; tables above stay permissive.
+    entry.set_frame(
+        frame,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::ACCESSED,
+    );
+
+    table
+}
+
+/// Changes one 4KiB page of the physical window in the active tables; empty
+/// `flags` unmap it. Only this CPU's TLB is flushed.
+pub fn set_page_flags(
+    addr: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    let lv4 = get_active_level_4_table();
+    let lv3 = frame_to_page_table(lv4[0].frame().unwrap());
+    let entry = split_to_4k(lv3, addr, frame_allocator);
+    if flags.is_empty() {
+        entry.set_unused();
+    } else {
+        entry.set_flags(flags);
+    }
+
+    x86_64::instructions::tlb::flush(VirtAddr::new(addr));
+}
+
 fn new_page_table(
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> (PhysFrame, *mut PageTable) {
     let frame = frame_allocator.allocate_frame().unwrap();
+    let table: *mut PageTable = VirtAddr::new(frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { table.write(PageTable::new()) };
 
-    (
-        frame,
-        VirtAddr::new(frame.start_address().as_u64()).as_mut_ptr(),
-    )
+    (frame, table)
 }
 
 pub fn get_active_level_4_table() -> &'static mut PageTable {
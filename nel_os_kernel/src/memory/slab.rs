@@ -0,0 +1,227 @@
+use core::{
+    alloc::Layout,
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
+
+use crate::{constant::PAGE_SIZE, memory::bitmap::GlobalFrameAllocator};
+
+/// Object sizes served from slabs; anything larger goes to the heap.
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+static CACHES: [Mutex<SlabCache>; SIZE_CLASSES.len()] = [
+    Mutex::new(SlabCache::new(SIZE_CLASSES[0])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[1])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[2])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[3])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[4])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[5])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[6])),
+    Mutex::new(SlabCache::new(SIZE_CLASSES[7])),
+];
+
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub object_size: usize,
+    /// 4KiB frames carved into objects. Slabs are never given back.
+    pub slabs: usize,
+    pub in_use: usize,
+}
+
+impl fmt::Display for SlabStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}B: {}/{} objects in {} slabs",
+            self.object_size,
+            self.in_use,
+            self.slabs * (PAGE_SIZE / self.object_size),
+            self.slabs
+        )
+    }
+}
+
+/// Fixed-size objects carved out of single frames, with an intrusive free list.
+pub struct SlabCache {
+    object_size: usize,
+    free: Option<NonNull<FreeObject>>,
+    slabs: usize,
+    in_use: usize,
+}
+
+// The free list only points into frames owned by this cache.
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    pub const fn new(object_size: usize) -> Self {
+        let object_size = if object_size < mem::size_of::<FreeObject>() {
+            mem::size_of::<FreeObject>()
+        } else {
+            object_size.next_multiple_of(mem::align_of::<FreeObject>())
+        };
+        assert!(object_size <= PAGE_SIZE, "slab objects must fit in a frame");
+
+        Self {
+            object_size,
+            free: None,
+            slabs: 0,
+            in_use: 0,
+        }
+    }
+
+    pub fn allocate(&mut self) -> Option<NonNull<u8>> {
+        if self.free.is_none() {
+            self.refill()?;
+        }
+
+        let object = self.free?;
+        self.free = unsafe { object.as_ref().next };
+        self.in_use += 1;
+
+        Some(object.cast())
+    }
+
+    /// # Safety
+    /// `ptr` must come from `allocate` on this cache and not be used afterwards.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+        let object = ptr.cast::<FreeObject>();
+        unsafe { object.write(FreeObject { next: self.free }) };
+        self.free = Some(object);
+        self.in_use -= 1;
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            object_size: self.object_size,
+            slabs: self.slabs,
+            in_use: self.in_use,
+        }
+    }
+
+    fn refill(&mut self) -> Option<()> {
+        let frame: PhysFrame<Size4KiB> = GlobalFrameAllocator.allocate_frame()?;
+        // Physical memory is identity mapped.
+        let base = frame.start_address().as_u64() as usize;
+
+        for offset in (0..PAGE_SIZE - self.object_size + 1)
+            .step_by(self.object_size)
+            .rev()
+        {
+            let object = (base + offset) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: self.free }) };
+            self.free = NonNull::new(object);
+        }
+        self.slabs += 1;
+
+        Some(())
+    }
+}
+
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| size <= class)
+}
+
+/// `None` when `layout` is too large for a size class or no frame is left;
+/// the caller then falls back to the heap.
+pub fn allocate(layout: Layout) -> Option<NonNull<u8>> {
+    CACHES[size_class(layout)?].lock().allocate()
+}
+
+/// # Safety
+/// `ptr` must come from `allocate` with the same `layout`.
+pub unsafe fn deallocate(ptr: NonNull<u8>, layout: Layout) {
+    let class = size_class(layout).expect("slab object with an oversized layout");
+    unsafe { CACHES[class].lock().deallocate(ptr) };
+}
+
+pub fn stats() -> impl Iterator<Item = SlabStats> {
+    CACHES.iter().map(|cache| cache.lock().stats())
+}
+
+/// A dedicated cache for one object type, e.g. `static TIMERS: Pool<Timer> = Pool::new();`.
+pub struct Pool<T> {
+    cache: Mutex<SlabCache>,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Sync for Pool<T> {}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Pool<T> {
+    pub const fn new() -> Self {
+        assert!(
+            mem::align_of::<T>() <= PAGE_SIZE,
+            "pool objects must not need more than frame alignment"
+        );
+
+        let size = if mem::size_of::<T>() > mem::align_of::<T>() {
+            mem::size_of::<T>()
+        } else {
+            mem::align_of::<T>()
+        };
+
+        Self {
+            cache: Mutex::new(SlabCache::new(size.next_multiple_of(mem::align_of::<T>()))),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn alloc(&self, value: T) -> Option<PoolBox<'_, T>> {
+        let ptr = self.cache.lock().allocate()?.cast::<T>();
+        unsafe { ptr.write(value) };
+
+        Some(PoolBox { ptr, pool: self })
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.cache.lock().stats()
+    }
+}
+
+/// Owns an object from a `Pool` and returns it there on drop.
+pub struct PoolBox<'a, T> {
+    ptr: NonNull<T>,
+    pool: &'a Pool<T>,
+}
+
+unsafe impl<T: Send> Send for PoolBox<'_, T> {}
+unsafe impl<T: Sync> Sync for PoolBox<'_, T> {}
+
+impl<T> Deref for PoolBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for PoolBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for PoolBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.ptr.drop_in_place();
+            self.pool.cache.lock().deallocate(self.ptr.cast());
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::constant::PAGE_SIZE;
+
+/// A stack with a page below it that `paging::init_page_table` leaves
+/// unmapped, so running off the end faults instead of corrupting whatever
+/// the linker placed next. Use it as a `static mut` and go through raw
+/// pointers (`&raw const STACK`).
+#[repr(C, align(4096))]
+pub struct GuardedStack<const SIZE: usize> {
+    guard: [u8; PAGE_SIZE],
+    stack: [u8; SIZE],
+}
+
+impl<const SIZE: usize> GuardedStack<SIZE> {
+    pub const fn new() -> Self {
+        assert!(SIZE % PAGE_SIZE == 0, "stack size must be page aligned");
+
+        Self {
+            guard: [0; PAGE_SIZE],
+            stack: [0; SIZE],
+        }
+    }
+
+    pub fn guard_page(this: *const Self) -> u64 {
+        this as u64
+    }
+
+    /// Initial stack pointer; 16-byte aligned.
+    pub fn top(this: *const Self) -> u64 {
+        this as u64 + (PAGE_SIZE + SIZE) as u64
+    }
+}
+
+impl<const SIZE: usize> Default for GuardedStack<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
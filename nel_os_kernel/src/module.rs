@@ -0,0 +1,71 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use nel_os_common::module::{Module, ModuleKind};
+use spin::Once;
+
+use crate::info;
+
+static MODULES: Once<Vec<BootModule>> = Once::new();
+
+pub struct BootModule {
+    pub name: String,
+    pub kind: ModuleKind,
+    pub addr: u64,
+    pub size: u64,
+}
+
+impl BootModule {
+    pub fn data(&self) -> &'static [u8] {
+        if self.size == 0 {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.addr as *const u8, self.size as usize) }
+    }
+}
+
+/// Copies the bootloader's module list onto the heap. Needs the allocator.
+pub fn init(modules: &[Module]) {
+    MODULES.call_once(|| {
+        modules
+            .iter()
+            .map(|module| BootModule {
+                name: module.name().to_string(),
+                kind: module.kind(),
+                addr: module.addr,
+                size: module.size,
+            })
+            .collect()
+    });
+
+    for module in all() {
+        info!(
+            "Module {} ({}): {:#x}, {}KiB",
+            module.name,
+            module.kind.name(),
+            module.addr,
+            module.size / 1024
+        );
+    }
+}
+
+pub fn all() -> &'static [BootModule] {
+    MODULES
+        .get()
+        .map(|modules| modules.as_slice())
+        .unwrap_or(&[])
+}
+
+pub fn find(name: &str) -> Option<&'static BootModule> {
+    all().iter().find(|module| module.name == name)
+}
+
+pub fn of_kind(kind: ModuleKind) -> impl Iterator<Item = &'static BootModule> {
+    all().iter().filter(move |module| module.kind == kind)
+}
+
+pub fn first_of_kind(kind: ModuleKind) -> Option<&'static BootModule> {
+    of_kind(kind).next()
+}
@@ -0,0 +1,200 @@
+use alloc::string::{String, ToString};
+use core::ptr::read_unaligned;
+use spin::Once;
+
+use crate::{info, warn};
+
+static SMBIOS: Once<Smbios> = Once::new();
+
+pub struct Smbios {
+    /// Entry point the tables were read from, for building the guest's DMI view.
+    pub entry_point: u64,
+    pub version: (u8, u8),
+    pub table_addr: u64,
+    pub table_len: usize,
+    pub system: SystemInfo,
+}
+
+#[derive(Default)]
+pub struct SystemInfo {
+    pub bios_vendor: String,
+    pub bios_version: String,
+    pub bios_date: String,
+    pub manufacturer: String,
+    pub product: String,
+    pub version: String,
+    pub serial: String,
+    pub uuid: Option<[u8; 16]>,
+}
+
+const TYPE_BIOS: u8 = 0;
+const TYPE_SYSTEM: u8 = 1;
+const TYPE_END: u8 = 127;
+
+/// Prefers the 64-bit SMBIOS 3 entry point when the firmware provides both.
+pub fn init(smbios: Option<u64>, smbios3: Option<u64>) {
+    let parsed = match (smbios3, smbios) {
+        (Some(entry), _) => unsafe { parse_entry3(entry) },
+        (None, Some(entry)) => unsafe { parse_entry(entry) },
+        (None, None) => {
+            warn!("No SMBIOS tables");
+            return;
+        }
+    };
+
+    let smbios = match parsed {
+        Ok(smbios) => SMBIOS.call_once(|| smbios),
+        Err(e) => {
+            warn!("Failed to read SMBIOS: {}", e);
+            return;
+        }
+    };
+
+    let system = &smbios.system;
+    info!(
+        "SMBIOS {}.{} at {:#x}",
+        smbios.version.0, smbios.version.1, smbios.entry_point
+    );
+    info!(
+        "Platform: {} {} ({})",
+        system.manufacturer, system.product, system.version
+    );
+    info!(
+        "BIOS: {} {} ({})",
+        system.bios_vendor, system.bios_version, system.bios_date
+    );
+    if let Some(uuid) = system.uuid {
+        info!(
+            "System UUID: {}",
+            nel_os_common::measurement::HexDigest(&uuid)
+        );
+    }
+}
+
+pub fn get() -> Option<&'static Smbios> {
+    SMBIOS.get()
+}
+
+unsafe fn parse_entry(entry: u64) -> Result<Smbios, &'static str> {
+    let ptr = entry as *const u8;
+    let anchor = unsafe { read_unaligned(ptr as *const [u8; 4]) };
+    if &anchor != b"_SM_" {
+        return Err("bad SMBIOS entry point anchor");
+    }
+
+    let length = unsafe { *ptr.add(0x05) } as usize;
+    if checksum(ptr, length) != 0 {
+        return Err("bad SMBIOS entry point checksum");
+    }
+
+    let version = unsafe { (*ptr.add(0x06), *ptr.add(0x07)) };
+    let table_len = unsafe { read_unaligned(ptr.add(0x16) as *const u16) } as usize;
+    let table_addr = unsafe { read_unaligned(ptr.add(0x18) as *const u32) } as u64;
+
+    Ok(Smbios {
+        entry_point: entry,
+        version,
+        table_addr,
+        table_len,
+        system: unsafe { read_system_info(table_addr, table_len) },
+    })
+}
+
+unsafe fn parse_entry3(entry: u64) -> Result<Smbios, &'static str> {
+    let ptr = entry as *const u8;
+    let anchor = unsafe { read_unaligned(ptr as *const [u8; 5]) };
+    if &anchor != b"_SM3_" {
+        return Err("bad SMBIOS3 entry point anchor");
+    }
+
+    let length = unsafe { *ptr.add(0x06) } as usize;
+    if checksum(ptr, length) != 0 {
+        return Err("bad SMBIOS3 entry point checksum");
+    }
+
+    let version = unsafe { (*ptr.add(0x07), *ptr.add(0x08)) };
+    let table_len = unsafe { read_unaligned(ptr.add(0x0C) as *const u32) } as usize;
+    let table_addr = unsafe { read_unaligned(ptr.add(0x10) as *const u64) };
+
+    Ok(Smbios {
+        entry_point: entry,
+        version,
+        table_addr,
+        table_len,
+        system: unsafe { read_system_info(table_addr, table_len) },
+    })
+}
+
+fn checksum(ptr: *const u8, len: usize) -> u8 {
+    (0..len).fold(0u8, |sum, i| sum.wrapping_add(unsafe { *ptr.add(i) }))
+}
+
+unsafe fn read_system_info(table_addr: u64, table_len: usize) -> SystemInfo {
+    let table = unsafe { core::slice::from_raw_parts(table_addr as *const u8, table_len) };
+    let mut system = SystemInfo::default();
+
+    let mut offset = 0;
+    while offset + 4 <= table.len() {
+        let ty = table[offset];
+        let length = table[offset + 1] as usize;
+        if length < 4 || offset + length > table.len() {
+            break;
+        }
+
+        let formatted = &table[offset..offset + length];
+        let Some(strings_len) = strings_len(&table[offset + length..]) else {
+            break;
+        };
+        let strings = &table[offset + length..offset + length + strings_len];
+        let string = |index: usize| {
+            formatted
+                .get(index)
+                .and_then(|&n| string_at(strings, n))
+                .unwrap_or("")
+                .to_string()
+        };
+
+        match ty {
+            TYPE_BIOS => {
+                system.bios_vendor = string(0x04);
+                system.bios_version = string(0x05);
+                system.bios_date = string(0x08);
+            }
+            TYPE_SYSTEM => {
+                system.manufacturer = string(0x04);
+                system.product = string(0x05);
+                system.version = string(0x06);
+                system.serial = string(0x07);
+                system.uuid = formatted
+                    .get(0x08..0x18)
+                    .and_then(|uuid| uuid.try_into().ok());
+            }
+            TYPE_END => break,
+            _ => {}
+        }
+
+        offset += length + strings_len;
+    }
+
+    system
+}
+
+/// The string set ends with a double NUL, which is also all an empty set contains.
+fn strings_len(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .windows(2)
+        .position(|pair| pair == [0, 0])
+        .map(|end| end + 2)
+}
+
+fn string_at(strings: &[u8], index: u8) -> Option<&str> {
+    if index == 0 {
+        return None;
+    }
+
+    strings
+        .split(|&b| b == 0)
+        .nth(index as usize - 1)
+        .and_then(|s| core::str::from_utf8(s).ok())
+        .map(str::trim)
+}
@@ -0,0 +1,196 @@
+pub mod percpu;
+mod trampoline;
+
+use acpi::{platform::ProcessorState, PlatformInfo};
+use alloc::{alloc::Global, boxed::Box};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use spin::Once;
+use x86_64::{
+    instructions::interrupts, registers::control::Cr3, structures::paging::PageTableFlags, VirtAddr,
+};
+
+use crate::{
+    constant::{AP_STACK_SIZE, PAGE_SIZE},
+    info,
+    interrupt::{
+        apic::{self, LocalApic, LAPIC},
+        gdt, idt,
+    },
+    memory::{
+        bitmap::{BitmapMemoryTable, GlobalFrameAllocator},
+        paging,
+    },
+    time::{self, Duration, Instant},
+    warn,
+};
+
+use self::trampoline::Trampoline;
+
+const AP_STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+static TRAMPOLINE_PAGE: Once<u64> = Once::new();
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+static AP_READY: AtomicBool = AtomicBool::new(false);
+
+/// Handed to `ap_main` through the trampoline.
+struct ApStartup {
+    index: usize,
+    apic_id: u32,
+    ist_stack_tops: [VirtAddr; gdt::IST_STACK_COUNT],
+}
+
+/// Claims a page below 1MiB for the AP trampoline. Must run right after the
+/// frame table is built, before page tables and the heap use up low memory.
+pub fn reserve_trampoline(table: &mut BitmapMemoryTable) {
+    match table.allocate_contiguous_below(1, 1, 0x10_0000) {
+        Some(addr) => {
+            TRAMPOLINE_PAGE.call_once(|| addr.as_u64());
+        }
+        None => {
+            warn!("No free page below 1MiB, APs cannot be started");
+        }
+    }
+}
+
+/// Sets up the BSP's per-CPU data. Needs the heap and the local APIC.
+pub fn init_bsp() {
+    let apic_id = LAPIC.get().map(LocalApic::id).unwrap_or(0);
+    percpu::init(0, apic_id);
+}
+
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::Acquire)
+}
+
+/// Starts every enabled AP in the MADT with INIT-SIPI-SIPI, one at a time.
+/// Stops at the first AP that fails, since the trampoline and its startup
+/// data are shared. Needs interrupts enabled on the BSP for the startup delays.
+pub fn start_aps(platform_info: &PlatformInfo<'_, Global>) {
+    let Some(processors) = &platform_info.processor_info else {
+        warn!("No processors in the MADT, running on the BSP only");
+        return;
+    };
+    let Some(&base) = TRAMPOLINE_PAGE.get() else {
+        return;
+    };
+    let Some(lapic) = LAPIC.get() else {
+        return;
+    };
+
+    let cr3 = Cr3::read().0.start_address().as_u64();
+    let Ok(cr3) = u32::try_from(cr3) else {
+        warn!("Page tables above 4GiB, APs cannot be started");
+        return;
+    };
+
+    // The physical window is NX; the trampoline page is the exception while APs start.
+    paging::set_page_flags(
+        base,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        &mut GlobalFrameAllocator,
+    );
+    let trampoline = unsafe { Trampoline::install(base, cr3) };
+
+    for processor in processors.application_processors.iter() {
+        if processor.state == ProcessorState::Disabled {
+            continue;
+        }
+
+        let index = online_cpus();
+        let apic_id = processor.local_apic_id;
+        match start_ap(lapic, &trampoline, index, apic_id) {
+            Ok(()) => {
+                ONLINE_CPUS.fetch_add(1, Ordering::AcqRel);
+            }
+            Err(e) => {
+                warn!("CPU {} (APIC {}): {}", index, apic_id, e);
+                warn!("Stopping AP bring-up");
+                break;
+            }
+        }
+    }
+
+    paging::set_page_flags(
+        base,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        &mut GlobalFrameAllocator,
+    );
+
+    info!("{} CPUs online", online_cpus());
+}
+
+fn start_ap(
+    lapic: &LocalApic,
+    trampoline: &Trampoline,
+    index: usize,
+    apic_id: u32,
+) -> Result<(), &'static str> {
+    let stack_top = allocate_stack(AP_STACK_SIZE)?;
+    let mut ist_stack_tops = [VirtAddr::zero(); gdt::IST_STACK_COUNT];
+    for top in &mut ist_stack_tops {
+        *top = VirtAddr::new(allocate_stack(gdt::IST_STACK_SIZE)?);
+    }
+    let startup = Box::leak(Box::new(ApStartup {
+        index,
+        apic_id,
+        ist_stack_tops,
+    }));
+
+    AP_READY.store(false, Ordering::Release);
+    trampoline.prepare(stack_top, ap_main, startup as *const ApStartup as u64);
+
+    lapic.send_init(apic_id);
+    time::wait_for_ms(10);
+    for _ in 0..2 {
+        lapic.send_startup(apic_id, trampoline.vector());
+        time::wait_for_ms(1);
+        if AP_READY.load(Ordering::Acquire) {
+            return Ok(());
+        }
+    }
+
+    let start = Instant::now();
+    while !AP_READY.load(Ordering::Acquire) {
+        if start.elapsed() > AP_STARTUP_TIMEOUT {
+            // Hold it in wait-for-SIPI so a late start cannot run on the
+            // trampoline, stack or index handed to another CPU.
+            lapic.send_init(apic_id);
+            return Err("did not respond to INIT-SIPI-SIPI, held in INIT");
+        }
+        spin_loop();
+    }
+
+    Ok(())
+}
+
+/// Frames for a stack plus an unmapped guard page below it. Returns the top.
+fn allocate_stack(size: usize) -> Result<u64, &'static str> {
+    let pages = size.div_ceil(PAGE_SIZE) + 1;
+    let base = GlobalFrameAllocator::with(|table| table.allocate_contiguous(pages, 1))
+        .flatten()
+        .ok_or("no memory for the AP stacks")?
+        .as_u64();
+
+    paging::set_page_flags(base, PageTableFlags::empty(), &mut GlobalFrameAllocator);
+
+    Ok(base + (pages * PAGE_SIZE) as u64)
+}
+
+extern "sysv64" fn ap_main(startup: u64) -> ! {
+    let startup = unsafe { &*(startup as *const ApStartup) };
+
+    gdt::init_ap(startup.ist_stack_tops);
+    idt::init_idt();
+    let cpu = percpu::init(startup.index, startup.apic_id);
+    apic::init_ap_local_apic();
+
+    AP_READY.store(true, Ordering::Release);
+    info!("CPU {} online (APIC {})", cpu.index, cpu.apic_id);
+
+    // Parked until work such as a vCPU is scheduled onto it.
+    interrupts::enable();
+    crate::hlt_loop();
+}
@@ -0,0 +1,58 @@
+use alloc::boxed::Box;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use x86_64::{registers::model_specific::GsBase, VirtAddr};
+
+/// Data owned by one CPU, reached through `GS_BASE`. The first field points
+/// back at the structure so `current` needs a single `gs:`-relative load.
+#[repr(C)]
+pub struct PerCpu {
+    this: *const PerCpu,
+    /// 0 for the BSP, then APs in MADT order.
+    pub index: usize,
+    pub apic_id: u32,
+    /// Local APIC timer interrupts taken on this CPU.
+    pub ticks: AtomicU64,
+}
+
+unsafe impl Sync for PerCpu {}
+
+impl PerCpu {
+    pub fn is_bsp(&self) -> bool {
+        self.index == 0
+    }
+
+    pub fn tick(&self) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Allocates this CPU's area and points `GS_BASE` at it. Needs the heap.
+pub fn init(index: usize, apic_id: u32) -> &'static PerCpu {
+    let cpu = Box::leak(Box::new(PerCpu {
+        this: core::ptr::null(),
+        index,
+        apic_id,
+        ticks: AtomicU64::new(0),
+    }));
+    cpu.this = cpu;
+
+    GsBase::write(VirtAddr::from_ptr(cpu));
+
+    cpu
+}
+
+/// `None` before `init` ran on this CPU.
+pub fn current() -> Option<&'static PerCpu> {
+    if GsBase::read().is_null() {
+        return None;
+    }
+
+    let cpu: *const PerCpu;
+    unsafe { asm!("mov {}, gs:[0]", out(reg) cpu, options(nostack, readonly, preserves_flags)) };
+
+    Some(unsafe { &*cpu })
+}
@@ -0,0 +1,157 @@
+//! Real-mode entry for application processors. The code is copied to a page
+//! below 1MiB whose number is the SIPI vector. It switches straight to long
+//! mode on the kernel's page tables and calls `entry(arg)` on `stack`.
+
+use core::{
+    arch::global_asm,
+    sync::atomic::{fence, Ordering},
+};
+
+global_asm!(
+    r#"
+    .pushsection .text.ap_trampoline, "ax"
+    .global ap_trampoline_start
+    .global ap_trampoline_end
+    .global ap_trampoline_gdt
+    .global ap_trampoline_gdt_ptr
+    .global ap_trampoline_far_ptr
+    .global ap_trampoline_long_mode
+    .global ap_trampoline_cr3
+    .global ap_trampoline_stack
+    .global ap_trampoline_entry
+    .global ap_trampoline_arg
+
+    .code16
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+
+    lgdtl (ap_trampoline_gdt_ptr - ap_trampoline_start)
+
+    mov %cr4, %eax
+    or $(1 << 5), %eax
+    mov %eax, %cr4
+
+    mov (ap_trampoline_cr3 - ap_trampoline_start), %eax
+    mov %eax, %cr3
+
+    mov $0xc0000080, %ecx
+    rdmsr
+    or $((1 << 8) | (1 << 11)), %eax
+    wrmsr
+
+    mov %cr0, %eax
+    or $0x80010001, %eax
+    mov %eax, %cr0
+
+    ljmpl *(ap_trampoline_far_ptr - ap_trampoline_start)
+
+    .code64
+ap_trampoline_long_mode:
+    mov $0x10, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    xor %ax, %ax
+    mov %ax, %fs
+    mov %ax, %gs
+
+    mov ap_trampoline_stack(%rip), %rsp
+    mov ap_trampoline_arg(%rip), %rdi
+    mov ap_trampoline_entry(%rip), %rax
+    xor %ebp, %ebp
+    call *%rax
+1:
+    hlt
+    jmp 1b
+
+    .balign 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff
+    .quad 0x00cf92000000ffff
+ap_trampoline_gdt_ptr:
+    .word 3 * 8 - 1
+    .long 0
+ap_trampoline_far_ptr:
+    .long 0
+    .word 0x08
+
+    .balign 8
+ap_trampoline_cr3:
+    .quad 0
+ap_trampoline_stack:
+    .quad 0
+ap_trampoline_entry:
+    .quad 0
+ap_trampoline_arg:
+    .quad 0
+ap_trampoline_end:
+    .popsection
+"#,
+    options(att_syntax)
+);
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdt_ptr: u8;
+    static ap_trampoline_far_ptr: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_arg: u8;
+}
+
+/// The trampoline copied to `base`, with its absolute addresses patched.
+pub struct Trampoline {
+    base: u64,
+}
+
+impl Trampoline {
+    /// # Safety
+    /// `base` must be a page-aligned, identity-mapped, executable page below 1MiB.
+    pub unsafe fn install(base: u64, cr3: u32) -> Self {
+        let start = &raw const ap_trampoline_start;
+        let len = &raw const ap_trampoline_end as usize - start as usize;
+        unsafe { core::ptr::copy_nonoverlapping(start, base as *mut u8, len) };
+
+        let trampoline = Self { base };
+        unsafe {
+            let gdt = trampoline.addr_of(&raw const ap_trampoline_gdt);
+            let long_mode = trampoline.addr_of(&raw const ap_trampoline_long_mode);
+            trampoline.write(&raw const ap_trampoline_gdt_ptr, 2, gdt as u32);
+            trampoline.write(&raw const ap_trampoline_far_ptr, 0, long_mode as u32);
+            trampoline.write(&raw const ap_trampoline_cr3, 0, cr3 as u64);
+        }
+
+        trampoline
+    }
+
+    pub fn vector(&self) -> u8 {
+        (self.base >> 12) as u8
+    }
+
+    /// Sets what the next AP to start will run.
+    pub fn prepare(&self, stack_top: u64, entry: extern "sysv64" fn(u64) -> !, arg: u64) {
+        unsafe {
+            self.write(&raw const ap_trampoline_stack, 0, stack_top);
+            self.write(&raw const ap_trampoline_entry, 0, entry as usize as u64);
+            self.write(&raw const ap_trampoline_arg, 0, arg);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    fn addr_of(&self, symbol: *const u8) -> u64 {
+        self.base + (symbol as u64 - &raw const ap_trampoline_start as u64)
+    }
+
+    unsafe fn write<T>(&self, symbol: *const u8, offset: u64, value: T) {
+        let addr = self.addr_of(symbol) + offset;
+        unsafe { (addr as *mut T).write_unaligned(value) };
+    }
+}
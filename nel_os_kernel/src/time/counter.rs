@@ -0,0 +1,140 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use acpi::{platform::PmTimer, HpetInfo};
+use x86_64::instructions::port::Port;
+
+const PM_TIMER_FREQ: u64 = 3_579_545;
+
+const HPET_CAPABILITIES: usize = 0;
+const HPET_CONFIG: usize = 0x010 / 8;
+const HPET_COUNTER: usize = 0x0f0 / 8;
+
+const HPET_COUNT_SIZE_CAP: u64 = 1 << 13;
+const HPET_ENABLE_CNF: u64 = 1;
+
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// A free-running hardware counter at a fixed frequency.
+pub enum Counter {
+    Hpet {
+        ptr: *mut u64,
+        frequency: u64,
+        mask: u64,
+    },
+    PmTimer {
+        port: u16,
+        mask: u64,
+    },
+}
+
+unsafe impl Send for Counter {}
+unsafe impl Sync for Counter {}
+
+impl Counter {
+    /// Starts the HPET main counter if the firmware left it stopped.
+    pub fn hpet(info: &HpetInfo) -> Option<Self> {
+        let ptr = info.base_address as *mut u64;
+        let capabilities = unsafe { ptr.add(HPET_CAPABILITIES).read_volatile() };
+        let period_fs = capabilities >> 32;
+        if period_fs == 0 || period_fs > 100_000_000 {
+            return None;
+        }
+
+        unsafe {
+            let config = ptr.add(HPET_CONFIG).read_volatile();
+            ptr.add(HPET_CONFIG)
+                .write_volatile(config | HPET_ENABLE_CNF);
+        }
+
+        Some(Counter::Hpet {
+            ptr,
+            frequency: FEMTOS_PER_SEC / period_fs,
+            mask: if capabilities & HPET_COUNT_SIZE_CAP != 0 {
+                u64::MAX
+            } else {
+                u32::MAX as u64
+            },
+        })
+    }
+
+    pub fn pm_timer(pm_timer: &PmTimer) -> Self {
+        Counter::PmTimer {
+            port: pm_timer.base.address as u16,
+            mask: if pm_timer.supports_32bit {
+                u32::MAX as u64
+            } else {
+                0x00ff_ffff
+            },
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::Hpet { .. } => "HPET",
+            Counter::PmTimer { .. } => "PM timer",
+        }
+    }
+
+    pub fn frequency(&self) -> u64 {
+        match *self {
+            Counter::Hpet { frequency, .. } => frequency,
+            Counter::PmTimer { .. } => PM_TIMER_FREQ,
+        }
+    }
+
+    pub fn mask(&self) -> u64 {
+        match *self {
+            Counter::Hpet { mask, .. } | Counter::PmTimer { mask, .. } => mask,
+        }
+    }
+
+    pub fn read(&self) -> u64 {
+        match *self {
+            Counter::Hpet { ptr, mask, .. } => unsafe {
+                ptr.add(HPET_COUNTER).read_volatile() & mask
+            },
+            Counter::PmTimer { port, mask } => unsafe {
+                Port::<u32>::new(port).read() as u64 & mask
+            },
+        }
+    }
+
+    /// Elapsed counts from `start` to `end`, allowing for one wrap.
+    pub fn delta(&self, start: u64, end: u64) -> u64 {
+        end.wrapping_sub(start) & self.mask()
+    }
+}
+
+/// Extends a narrow counter to 64 bits. It must be read at least once per
+/// wrap period (about 4.7s for a 24-bit PM timer), which the BSP timer tick does.
+pub struct ExtendedCounter {
+    counter: Counter,
+    last: AtomicU64,
+}
+
+impl ExtendedCounter {
+    pub fn new(counter: Counter) -> Self {
+        let last = AtomicU64::new(counter.read());
+        Self { counter, last }
+    }
+
+    pub fn counter(&self) -> &Counter {
+        &self.counter
+    }
+
+    pub fn read(&self) -> u64 {
+        let mut last = self.last.load(Ordering::Acquire);
+        loop {
+            let now = last + self.counter.delta(last, self.counter.read());
+            match self
+                .last
+                .compare_exchange_weak(last, now, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return now,
+                // Another CPU moved it forward; never go backwards.
+                Err(current) if current >= now => return current,
+                Err(current) => last = current,
+            }
+        }
+    }
+}
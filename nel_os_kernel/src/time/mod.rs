@@ -0,0 +1,239 @@
+mod counter;
+pub mod timer;
+
+use core::{
+    arch::{asm, x86_64::_rdtsc},
+    fmt,
+    ops::{Add, Sub},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub use core::time::Duration;
+
+use acpi::{platform::PmTimer, HpetInfo};
+use raw_cpuid::CpuId;
+use spin::Once;
+
+use crate::{info, warn};
+
+use self::counter::{Counter, ExtendedCounter};
+
+/// BSP local APIC timer interrupts, one per millisecond.
+pub static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+static CLOCK: Once<Clock> = Once::new();
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const TSC_CALIBRATION_MS: u64 = 50;
+
+enum Clock {
+    /// `ns = (tsc - base) * mult >> 32`.
+    Tsc {
+        base: u64,
+        mult: u64,
+        frequency: u64,
+    },
+    Counter {
+        counter: ExtendedCounter,
+        base: u64,
+    },
+}
+
+impl Clock {
+    fn now(&self) -> u64 {
+        match self {
+            Clock::Tsc { base, mult, .. } => {
+                let tsc = unsafe { _rdtsc() }.saturating_sub(*base);
+                ((tsc as u128 * *mult as u128) >> 32) as u64
+            }
+            Clock::Counter { counter, base } => {
+                let counts = counter.read() - base;
+                (counts as u128 * NANOS_PER_SEC as u128 / counter.counter().frequency() as u128)
+                    as u64
+            }
+        }
+    }
+}
+
+/// A point on the monotonic clock, in nanoseconds since `init`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Readable from any context, with or without interrupts. Before `init`
+    /// it falls back to timer ticks.
+    pub fn now() -> Self {
+        match CLOCK.get() {
+            Some(clock) => Instant(clock.now()),
+            None => Instant(get_ticks() as u64 * NANOS_PER_MILLI),
+        }
+    }
+
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Instant(nanos)
+    }
+
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Zero if `earlier` is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        self.0.checked_add(nanos).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+impl fmt::Display for Instant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:09}",
+            self.0 / NANOS_PER_SEC,
+            self.0 % NANOS_PER_SEC
+        )
+    }
+}
+
+/// Picks the clock source: invariant TSC calibrated against the HPET or PM
+/// timer, else the HPET, else the PM timer. Interrupts may still be disabled.
+pub fn init(hpet: Option<HpetInfo>, pm_timer: Option<&PmTimer>) {
+    let reference = hpet
+        .as_ref()
+        .and_then(Counter::hpet)
+        .or_else(|| pm_timer.map(Counter::pm_timer));
+    let Some(reference) = reference else {
+        warn!("No HPET or PM timer, timestamps come from the timer tick");
+        return;
+    };
+
+    let invariant_tsc = CpuId::new()
+        .get_advanced_power_mgmt_info()
+        .is_some_and(|apm| apm.has_invariant_tsc());
+
+    let clock = if invariant_tsc {
+        let frequency = calibrate_tsc(&reference);
+        info!(
+            "Clock: invariant TSC at {} kHz (calibrated against {})",
+            frequency / 1000,
+            reference.name()
+        );
+        Clock::Tsc {
+            base: unsafe { _rdtsc() },
+            mult: ((NANOS_PER_SEC as u128) << 32).div_ceil(frequency as u128) as u64,
+            frequency,
+        }
+    } else {
+        info!(
+            "Clock: {} at {} Hz",
+            reference.name(),
+            reference.frequency()
+        );
+        let counter = ExtendedCounter::new(reference);
+        let base = counter.read();
+        Clock::Counter { counter, base }
+    };
+
+    CLOCK.call_once(|| clock);
+}
+
+fn calibrate_tsc(reference: &Counter) -> u64 {
+    let counts = reference.frequency() * TSC_CALIBRATION_MS / 1000;
+
+    let start = reference.read();
+    let tsc_start = unsafe { _rdtsc() };
+    let mut elapsed;
+    loop {
+        elapsed = reference.delta(start, reference.read());
+        if elapsed >= counts {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    let tsc_end = unsafe { _rdtsc() };
+
+    ((tsc_end - tsc_start) as u128 * reference.frequency() as u128 / elapsed as u128) as u64
+}
+
+/// The invariant TSC frequency in Hz, when it is the clock source.
+pub fn tsc_frequency() -> Option<u64> {
+    match CLOCK.get()? {
+        Clock::Tsc { frequency, .. } => Some(*frequency),
+        Clock::Counter { .. } => None,
+    }
+}
+
+pub fn uptime() -> Duration {
+    Duration::from_nanos(Instant::now().as_nanos())
+}
+
+#[inline(always)]
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Release);
+
+    // Keeps a narrow fallback counter from wrapping unnoticed.
+    if let Some(Clock::Counter { counter, .. }) = CLOCK.get() {
+        counter.read();
+    }
+}
+
+#[inline(always)]
+pub fn get_ticks() -> usize {
+    TICKS.load(Ordering::Acquire)
+}
+
+/// Busy-waits; does not need interrupts once the clock is initialized.
+pub fn delay(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        core::hint::spin_loop();
+    }
+}
+
+#[inline(always)]
+pub fn wait_for_ms(ms: usize) {
+    if CLOCK.get().is_some() {
+        delay(Duration::from_millis(ms as u64));
+        return;
+    }
+
+    let start = get_ticks();
+    while get_ticks() - start < ms {
+        unsafe {
+            asm!("nop");
+        }
+    }
+}
+
+/// Runs `f` and returns how long it took, e.g. for VM exit profiling.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
@@ -0,0 +1,312 @@
+//! One-shot and periodic callbacks on a hashed timer wheel, advanced by the
+//! BSP's 1ms LAPIC timer tick.
+//!
+//! Callbacks run in that interrupt handler, on the BSP, with interrupts
+//! disabled. A callback must:
+//! - return quickly and never block, sleep or call `wait_for_ms`;
+//! - not allocate or free, since the heap is not interrupt-safe;
+//! - only take locks that are always held with interrupts disabled.
+//!
+//! It may schedule timers and cancel others, but must not drop its own
+//! handle. Heavier work should set a flag or queue an item for the code that
+//! owns the device.
+//!
+//! The context is shared with the `TimerHandle`, which cancels the timer when
+//! dropped, so a callback never outlives what it points at.
+
+use alloc::sync::Arc;
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    smp::percpu,
+    time::{get_ticks, Duration},
+};
+
+const WHEEL_SIZE: usize = 256;
+const MAX_TIMERS: usize = 128;
+
+static WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+
+const NOT_RUNNING: u64 = u64::MAX;
+/// `timer_id` of the callback `run_expired` is executing.
+static RUNNING: AtomicU64 = AtomicU64::new(NOT_RUNNING);
+
+fn timer_id(slot: u16, generation: u32) -> u64 {
+    ((slot as u64) << 32) | generation as u64
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    /// Absolute tick at which the timer fires.
+    deadline: u64,
+    /// Re-armed this many ticks later after firing; 0 for one-shot timers.
+    period: u64,
+    /// `call::<T>` for the context type the timer was scheduled with.
+    call: unsafe fn(*const (), *const ()),
+    callback: *const (),
+    context: *const (),
+}
+
+/// Calls `callback` (a `fn(&T)`) with `context` (an `Arc<T>` kept alive by the handle).
+unsafe fn call<T>(callback: *const (), context: *const ()) {
+    let callback: fn(&T) = unsafe { core::mem::transmute(callback) };
+    callback(unsafe { &*(context as *const T) });
+}
+
+struct Slot {
+    generation: u32,
+    timer: Option<Timer>,
+    /// Next slot in the same bucket.
+    next: Option<u16>,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot {
+        generation: 0,
+        timer: None,
+        next: None,
+    };
+}
+
+struct Wheel {
+    slots: [Slot; MAX_TIMERS],
+    buckets: [Option<u16>; WHEEL_SIZE],
+    /// Last tick whose bucket was processed.
+    now: u64,
+}
+
+// Contexts are `Send + Sync` and handed back to their callbacks only.
+unsafe impl Send for Wheel {}
+
+impl Wheel {
+    const fn new() -> Self {
+        Self {
+            slots: [const { Slot::EMPTY }; MAX_TIMERS],
+            buckets: [None; WHEEL_SIZE],
+            now: 0,
+        }
+    }
+
+    /// Returns the slot and generation of the new timer.
+    fn insert(&mut self, timer: Timer) -> Result<(u16, u32), &'static str> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.timer.is_none())
+            .ok_or("No free timer slots")?;
+
+        let slot = &mut self.slots[index];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.timer = Some(timer);
+        let generation = slot.generation;
+        self.link(index as u16);
+
+        Ok((index as u16, generation))
+    }
+
+    fn link(&mut self, index: u16) {
+        let deadline = self.slots[index as usize].timer.unwrap().deadline;
+        let bucket = &mut self.buckets[deadline as usize % WHEEL_SIZE];
+        self.slots[index as usize].next = *bucket;
+        *bucket = Some(index);
+    }
+
+    fn unlink(&mut self, index: u16) {
+        let deadline = self.slots[index as usize].timer.unwrap().deadline;
+        let bucket = deadline as usize % WHEEL_SIZE;
+        let next = self.slots[index as usize].next.take();
+
+        if self.buckets[bucket] == Some(index) {
+            self.buckets[bucket] = next;
+            return;
+        }
+
+        let mut cursor = self.buckets[bucket];
+        while let Some(current) = cursor {
+            if self.slots[current as usize].next == Some(index) {
+                self.slots[current as usize].next = next;
+                return;
+            }
+            cursor = self.slots[current as usize].next;
+        }
+    }
+
+    fn is_live(&self, slot: u16, generation: u32) -> bool {
+        let entry = &self.slots[slot as usize];
+        entry.generation == generation && entry.timer.is_some()
+    }
+
+    fn cancel(&mut self, slot: u16, generation: u32) -> bool {
+        if !self.is_live(slot, generation) {
+            return false;
+        }
+
+        self.unlink(slot);
+        self.slots[slot as usize].timer = None;
+        true
+    }
+
+    /// Takes one timer that is due at `self.now`, re-arming it if periodic,
+    /// and returns it with its `timer_id`. Periodic timers are re-armed from
+    /// `target` so a stall does not cause a burst.
+    fn pop_expired(&mut self, target: u64) -> Option<(u64, Timer)> {
+        let bucket = self.now as usize % WHEEL_SIZE;
+
+        let mut cursor = self.buckets[bucket];
+        while let Some(index) = cursor {
+            let timer = self.slots[index as usize].timer.unwrap();
+            cursor = self.slots[index as usize].next;
+            if timer.deadline > self.now {
+                // Due on a later lap of the wheel.
+                continue;
+            }
+
+            let id = timer_id(index, self.slots[index as usize].generation);
+            self.unlink(index);
+            if timer.period == 0 {
+                self.slots[index as usize].timer = None;
+            } else {
+                let slot = &mut self.slots[index as usize];
+                slot.timer = Some(Timer {
+                    deadline: self.now.max(target) + timer.period,
+                    ..timer
+                });
+                self.link(index);
+            }
+
+            return Some((id, timer));
+        }
+
+        None
+    }
+}
+
+/// Keeps a timer scheduled and its context alive; dropping it cancels the
+/// timer and waits for a callback running on another CPU to return.
+#[must_use = "dropping a TimerHandle cancels the timer immediately"]
+pub struct TimerHandle {
+    slot: u16,
+    generation: u32,
+    _context: Arc<dyn Send + Sync>,
+}
+
+impl TimerHandle {
+    /// `false` if the timer already fired (one-shot).
+    pub fn cancel(self) -> bool {
+        self.stop()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        interrupts::without_interrupts(|| WHEEL.lock().is_live(self.slot, self.generation))
+    }
+
+    fn stop(&self) -> bool {
+        let cancelled =
+            interrupts::without_interrupts(|| WHEEL.lock().cancel(self.slot, self.generation));
+
+        // Callbacks run on the BSP in its timer interrupt, so there they have
+        // always returned by the time normal code runs.
+        let on_bsp = percpu::current().is_none_or(|cpu| cpu.is_bsp());
+        if !on_bsp {
+            while RUNNING.load(Ordering::Acquire) == timer_id(self.slot, self.generation) {
+                spin_loop();
+            }
+        }
+
+        cancelled
+    }
+}
+
+impl Drop for TimerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn to_ticks(duration: Duration) -> u64 {
+    // Round up so a timer never fires early; the current tick is already partly over.
+    (duration.as_nanos().div_ceil(1_000_000) as u64).max(1)
+}
+
+fn add<T: Send + Sync + 'static>(
+    delay: u64,
+    period: u64,
+    callback: fn(&T),
+    context: Arc<T>,
+) -> Result<TimerHandle, &'static str> {
+    let timer = Timer {
+        deadline: 0,
+        period,
+        call: call::<T>,
+        callback: callback as *const (),
+        context: Arc::as_ptr(&context) as *const (),
+    };
+
+    let (slot, generation) = interrupts::without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let deadline = wheel.now.max(get_ticks() as u64) + delay;
+        wheel.insert(Timer { deadline, ..timer })
+    })?;
+
+    Ok(TimerHandle {
+        slot,
+        generation,
+        _context: context,
+    })
+}
+
+/// Calls `callback(&context)` once, `delay` from now.
+pub fn schedule<T: Send + Sync + 'static>(
+    delay: Duration,
+    callback: fn(&T),
+    context: Arc<T>,
+) -> Result<TimerHandle, &'static str> {
+    add(to_ticks(delay), 0, callback, context)
+}
+
+/// Calls `callback(&context)` every `period` until cancelled. Periods missed
+/// while interrupts were off are skipped, not made up with extra calls.
+pub fn schedule_periodic<T: Send + Sync + 'static>(
+    period: Duration,
+    callback: fn(&T),
+    context: Arc<T>,
+) -> Result<TimerHandle, &'static str> {
+    let period = to_ticks(period);
+    add(period, period, callback, context)
+}
+
+/// Runs every timer that is due. Called from the BSP timer interrupt.
+pub fn run_expired() {
+    let target = get_ticks() as u64;
+
+    loop {
+        let timer = {
+            let mut wheel = WHEEL.lock();
+            loop {
+                if let Some((id, timer)) = wheel.pop_expired(target) {
+                    // Under the lock, so a handle cancelled after this sees it.
+                    RUNNING.store(id, Ordering::Release);
+                    break Some(timer);
+                }
+                if wheel.now >= target {
+                    break None;
+                }
+                wheel.now += 1;
+            }
+        };
+
+        match timer {
+            Some(timer) => {
+                unsafe { (timer.call)(timer.callback, timer.context) };
+                RUNNING.store(NOT_RUNNING, Ordering::Release);
+            }
+            None => break,
+        }
+    }
+}
@@ -2,6 +2,7 @@ use ::x86_64::structures::paging::{FrameAllocator, Size4KiB};
 use alloc::boxed::Box;
 
 use crate::{
+    memory::LargeFrameAllocator,
     platform,
     vmm::x86_64::{amd::vcpu::AMDVCpu, intel::vcpu::IntelVCpu},
 };
@@ -17,10 +18,7 @@ pub trait VCpu {
     where
         Self: Sized;
 
-    fn run(
-        &mut self,
-        frame_allocator: &mut dyn FrameAllocator<Size4KiB>,
-    ) -> Result<(), &'static str>;
+    fn run(&mut self, frame_allocator: &mut dyn LargeFrameAllocator) -> Result<(), &'static str>;
 
     fn write_memory(&mut self, addr: u64, data: u8) -> Result<(), &'static str>;
     fn write_memory_ranged(
@@ -8,7 +8,9 @@ use x86_64::{
 };
 
 use crate::{
-    error, info,
+    crash, error, info,
+    memory::LargeFrameAllocator,
+    trace,
     vmm::{
         x86_64::{
             amd::vmcb::{InterceptVector1, InterceptVector2, Vmcb, VmcbSegment},
@@ -88,10 +90,7 @@ impl AMDVCpu {
 }
 
 impl VCpu for AMDVCpu {
-    fn run(
-        &mut self,
-        _frame_allocator: &mut dyn FrameAllocator<Size4KiB>,
-    ) -> Result<(), &'static str> {
+    fn run(&mut self, _frame_allocator: &mut dyn LargeFrameAllocator) -> Result<(), &'static str> {
         interrupts::without_interrupts(|| unsafe {
             if !self.initialized {
                 self.setup().expect("Failed to setup AMD VCPU");
@@ -107,8 +106,13 @@ impl VCpu for AMDVCpu {
             write_msr(0xC001_0117, self.hsave.start_address().as_u64());
 
             super::vmrun(self.vmcb.frame.start_address().as_u64());
+            crash::record_vm_exit(
+                vmcb.control_area.exit_code as u32,
+                vmcb.control_area.exit_info1,
+                vmcb.state_save_area.rip,
+            );
 
-            info!(
+            trace!(
                 "VMEXIT: code={:#x} info1={:#x} info2={:#x} next_rip={:#x}",
                 vmcb.control_area.exit_code,
                 vmcb.control_area.exit_info1,
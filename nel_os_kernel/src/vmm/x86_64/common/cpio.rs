@@ -0,0 +1,124 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+const NEWC_MAGIC: &str = "070701";
+const TRAILER: &str = "TRAILER!!!";
+
+pub const MODE_DIR: u32 = 0o040_755;
+pub const MODE_FILE: u32 = 0o100_644;
+pub const MODE_EXEC: u32 = 0o100_755;
+
+/// Builds an uncompressed "newc" cpio archive, the format the Linux
+/// initramfs unpacker accepts after (or instead of) a compressed one.
+pub struct NewcArchive {
+    data: Vec<u8>,
+    dirs: Vec<String>,
+    next_ino: u32,
+}
+
+impl Default for NewcArchive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NewcArchive {
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            dirs: Vec::new(),
+            next_ino: 1,
+        }
+    }
+
+    /// Adds a file, creating any missing parent directories first.
+    pub fn add_file(&mut self, path: &str, mode: u32, contents: &[u8]) -> Result<(), &'static str> {
+        let path = normalize(path)?;
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.add_dir(parent)?;
+        }
+        self.add_entry(path, mode, contents);
+
+        Ok(())
+    }
+
+    pub fn add_dir(&mut self, path: &str) -> Result<(), &'static str> {
+        let path = normalize(path)?;
+        let mut end = 0;
+        for component in path.split('/') {
+            end += component.len();
+            let dir = &path[..end];
+            if !self.dirs.iter().any(|existing| existing == dir) {
+                self.dirs.push(String::from(dir));
+                self.add_entry(dir, MODE_DIR, &[]);
+            }
+            end += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.add_header(TRAILER, 0, 0, 0);
+        self.data
+    }
+
+    fn add_entry(&mut self, path: &str, mode: u32, contents: &[u8]) {
+        let nlink = if mode == MODE_DIR { 2 } else { 1 };
+        self.add_header(path, mode, nlink, contents.len() as u32);
+        self.data.extend_from_slice(contents);
+        self.pad();
+    }
+
+    fn add_header(&mut self, path: &str, mode: u32, nlink: u32, file_size: u32) {
+        let ino = if path == TRAILER { 0 } else { self.next_ino };
+        self.next_ino += 1;
+
+        let fields = [
+            ino,
+            mode,
+            0, // uid
+            0, // gid
+            nlink,
+            0, // mtime
+            file_size,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            path.len() as u32 + 1,
+            0, // check
+        ];
+
+        let mut header = String::with_capacity(110);
+        header.push_str(NEWC_MAGIC);
+        for field in fields {
+            let _ = write!(header, "{field:08X}");
+        }
+
+        self.data.extend_from_slice(header.as_bytes());
+        self.data.extend_from_slice(path.as_bytes());
+        self.data.push(0);
+        self.pad();
+    }
+
+    fn pad(&mut self) {
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+    }
+}
+
+/// Archive paths are relative to the initramfs root and must not escape it.
+fn normalize(path: &str) -> Result<&str, &'static str> {
+    let path = path.trim_start_matches('/').trim_end_matches('/');
+    if path.is_empty() {
+        return Err("empty cpio path");
+    }
+    if path
+        .split('/')
+        .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        return Err("invalid cpio path");
+    }
+
+    Ok(path)
+}
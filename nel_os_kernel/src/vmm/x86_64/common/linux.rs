@@ -1,23 +1,43 @@
+use alloc::{format, vec::Vec};
 use core::ptr::read_unaligned;
 
-use crate::{info, vmm::VCpu, BZIMAGE_ADDR, BZIMAGE_SIZE};
+use nel_os_common::module::ModuleKind;
+
+use crate::{
+    info, module,
+    vmm::{x86_64::common::cpio, VCpu},
+    GUEST_CMDLINE, GUEST_PROFILE, GUEST_VM_CONF,
+};
 
 pub fn load_kernel(vcpu: &mut dyn VCpu) -> Result<(), &'static str> {
     info!("Loading kernel into guest memory");
-    let kernel_addr = BZIMAGE_ADDR.get().unwrap();
-    let kernel_size = BZIMAGE_SIZE.get().unwrap();
-
-    let kernel =
-        unsafe { core::slice::from_raw_parts(*kernel_addr as *const u8, *kernel_size as usize) };
-
-    let initrd_addr = crate::ROOTFS_ADDR.get().unwrap();
-    let initrd_size = crate::ROOTFS_SIZE.get().unwrap();
+    let kernel = module::first_of_kind(ModuleKind::Kernel)
+        .ok_or("No guest kernel module was loaded")?
+        .data();
+    let initrd = module::first_of_kind(ModuleKind::Initrd)
+        .map(|initrd| initrd.data())
+        .unwrap_or(&[]);
+    if initrd.is_empty() {
+        info!("No initrd, the guest only gets the overlay");
+    }
 
-    let initrd =
-        unsafe { core::slice::from_raw_parts(*initrd_addr as *const u8, *initrd_size as usize) };
+    // The unpacker skips zero padding between archives, so the overlay
+    // starts at the next 4-byte boundary after the initrd.
+    let overlay = build_overlay()?;
+    let overlay_addr = LAYOUT_INITRD + (initrd.len() as u64).next_multiple_of(4);
+    let ramdisk_size = overlay_addr - LAYOUT_INITRD + overlay.len() as u64;
+    info!(
+        "Initramfs: {} bytes of initrd + {} bytes of overlay",
+        initrd.len(),
+        overlay.len()
+    );
 
     info!("Creating boot parameters");
     let guest_mem_size = vcpu.get_guest_memory_size();
+    if LAYOUT_INITRD + ramdisk_size > guest_mem_size {
+        return Err("Guest memory is too small for initrd");
+    }
+
     let mut bp = BootParams::from_bytes(kernel)?;
     bp.e820_entries = 0;
 
@@ -30,9 +50,9 @@ pub fn load_kernel(vcpu: &mut dyn VCpu) -> Result<(), &'static str> {
     bp.hdr.cmd_line_ptr = LAYOUT_CMDLINE as u32;
     bp.hdr.vid_mode = 0xFFFF;
     bp.hdr.ramdisk_image = LAYOUT_INITRD as u32;
-    bp.hdr.ramdisk_size = initrd.len() as u32;
+    bp.hdr.ramdisk_size = ramdisk_size as u32;
 
-    bp.add_e820_entry(0, LAYOUT_KERNEL_BASE, E820Type::Ram);
+    add_low_memory_e820_entries(&mut bp);
     bp.add_e820_entry(
         LAYOUT_KERNEL_BASE,
         guest_mem_size - LAYOUT_KERNEL_BASE,
@@ -49,8 +69,11 @@ pub fn load_kernel(vcpu: &mut dyn VCpu) -> Result<(), &'static str> {
     let cmdline_start = LAYOUT_CMDLINE;
     let cmdline_end = cmdline_start + cmdline_max_size as u64;
     vcpu.write_memory_ranged(cmdline_start, cmdline_end, 0)?;
-    let cmdline_val = "console=ttyS0 earlyprintk=serial nokaslr";
+    let cmdline_val = GUEST_CMDLINE.get().map(|s| s.as_str()).unwrap_or("");
     let cmdline_bytes = cmdline_val.as_bytes();
+    if cmdline_bytes.len() >= cmdline_max_size as usize {
+        return Err("Command line is too long");
+    }
     for (i, &byte) in cmdline_bytes.iter().enumerate() {
         vcpu.write_memory(cmdline_start + i as u64, byte)?;
     }
@@ -73,12 +96,68 @@ pub fn load_kernel(vcpu: &mut dyn VCpu) -> Result<(), &'static str> {
         LAYOUT_KERNEL_BASE as usize,
     )?;
 
-    info!("Loading initrd image into guest memory");
-    load_image(vcpu, initrd, LAYOUT_INITRD as usize)?;
+    if !initrd.is_empty() {
+        info!(
+            "Loading initrd image ({} bytes) into guest memory",
+            initrd.len()
+        );
+        load_image(vcpu, initrd, LAYOUT_INITRD as usize)?;
+    }
+
+    info!("Loading initramfs overlay into guest memory");
+    vcpu.write_memory_ranged(LAYOUT_INITRD + initrd.len() as u64, overlay_addr, 0)?;
+    load_image(vcpu, &overlay, overlay_addr as usize)?;
 
     Ok(())
 }
 
+/// A cpio archive carrying `/etc/nel_os/vm.conf`, `/etc/nel_os/vm_id` and every
+/// overlay module, so the guest learns its identity without a rootfs rebuild.
+fn build_overlay() -> Result<Vec<u8>, &'static str> {
+    let profile = GUEST_PROFILE.get().map(|s| s.as_str()).unwrap_or("");
+    let vm_conf = GUEST_VM_CONF.get().map(|s| s.as_str()).unwrap_or("");
+    let configured_id = vm_conf.lines().find_map(|line| line.strip_prefix("id="));
+    let vm_id = configured_id.unwrap_or(profile);
+
+    let mut conf = format!("profile={profile}\n");
+    if configured_id.is_none() {
+        conf.push_str(&format!("id={vm_id}\n"));
+    }
+    conf.push_str(vm_conf);
+
+    let mut archive = cpio::NewcArchive::new();
+    archive.add_file("/etc/nel_os/vm.conf", cpio::MODE_FILE, conf.as_bytes())?;
+    archive.add_file(
+        "/etc/nel_os/vm_id",
+        cpio::MODE_FILE,
+        format!("{vm_id}\n").as_bytes(),
+    )?;
+
+    for module in module::of_kind(ModuleKind::Overlay) {
+        let data = module.data();
+        let mode = if data.starts_with(b"#!") {
+            cpio::MODE_EXEC
+        } else {
+            cpio::MODE_FILE
+        };
+        info!("Overlay: {} ({} bytes)", module.name, data.len());
+        archive.add_file(&module.name, mode, data)?;
+    }
+
+    Ok(archive.finish())
+}
+
+/// Low memory as a PC guest expects it: RAM up to the EBDA, then the
+/// EBDA, VGA and BIOS hole up to 1MiB. All of it is the guest's own memory.
+fn add_low_memory_e820_entries(bp: &mut BootParams) {
+    bp.add_e820_entry(0, LAYOUT_EBDA, E820Type::Ram);
+    bp.add_e820_entry(
+        LAYOUT_EBDA,
+        LAYOUT_KERNEL_BASE - LAYOUT_EBDA,
+        E820Type::Reserved,
+    );
+}
+
 fn load_image(vcpu: &mut dyn VCpu, image: &[u8], addr: usize) -> Result<(), &'static str> {
     info!(
         "Loading image at address {:#x}, size: {} bytes",
@@ -95,6 +174,7 @@ fn load_image(vcpu: &mut dyn VCpu, image: &[u8], addr: usize) -> Result<(), &'st
 
 pub const LAYOUT_BOOTPARAM: u64 = 0x0001_0000;
 pub const LAYOUT_CMDLINE: u64 = 0x0002_0000;
+pub const LAYOUT_EBDA: u64 = 0x0009_fc00;
 pub const LAYOUT_KERNEL_BASE: u64 = 0x0010_0000;
 pub const LAYOUT_INITRD: u64 = 0x0800_0000;
 
@@ -179,6 +259,14 @@ impl BootParams {
     }
 
     pub fn add_e820_entry(&mut self, addr: u64, size: u64, type_: E820Type) {
+        if let Some(last) = self.e820_entries.checked_sub(1) {
+            let last = &mut self.e820_map[last as usize];
+            if last.addr + last.size == addr && last.type_ == type_ as u32 {
+                last.size += size;
+                return;
+            }
+        }
+
         self.e820_map[self.e820_entries as usize].addr = addr;
         self.e820_map[self.e820_entries as usize].size = size;
         self.e820_map[self.e820_entries as usize].type_ = type_ as u32;
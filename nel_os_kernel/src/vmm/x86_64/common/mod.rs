@@ -1,3 +1,4 @@
+pub mod cpio;
 pub mod linux;
 pub mod segment;
 
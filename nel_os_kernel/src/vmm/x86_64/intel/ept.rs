@@ -39,7 +39,6 @@ impl Ept {
         }
     }
 
-    #[allow(dead_code)]
     pub fn map_2m(
         &mut self,
         gpa: u64,
@@ -1,3 +1,6 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU16, Ordering};
+
 use x86::{
     io::inb,
     vmx::{self, vmcs},
@@ -7,23 +10,36 @@ use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
 use super::qual::QualIo;
 use crate::{
     info,
-    interrupt::subscriber::InterruptContext,
+    interrupt::{idt::IRQ_TIMER, subscriber::InterruptContext},
     serial,
     vmm::x86_64::intel::{
         register::GuestRegisters, vmcs::controls::EntryIntrInfo, vmread, vmwrite,
     },
 };
 
-pub fn vmm_interrupt_subscriber(vcpu_ptr: *mut core::ffi::c_void, context: &InterruptContext) {
-    if vcpu_ptr.is_null() {
-        return;
+/// Guest PIC IRQ lines raised by host interrupts or device models and not yet
+/// injected. Shared with interrupt subscribers, so it is atomic.
+#[derive(Debug, Default)]
+pub struct PendingIrqs(AtomicU16);
+
+impl PendingIrqs {
+    pub fn raise(&self, irq: u8) {
+        self.0.fetch_or(1 << irq, Ordering::AcqRel);
+    }
+
+    pub fn clear(&self, irq: u8) {
+        self.0.fetch_and(!(1 << irq), Ordering::AcqRel);
     }
 
-    let vcpu = unsafe { &mut *(vcpu_ptr as *mut super::vcpu::IntelVCpu) };
+    pub fn get(&self) -> u16 {
+        self.0.load(Ordering::Acquire)
+    }
+}
 
-    if 0x20 <= context.vector && context.vector <= 0x20 + 16 {
-        let irq = context.vector - 0x20;
-        vcpu.pic.pending_irq |= 1 << irq;
+/// The BSP's host timer tick drives the guest's IRQ 0.
+pub fn vmm_interrupt_subscriber(pending_irq: &PendingIrqs, context: &InterruptContext) {
+    if context.vector == IRQ_TIMER as u8 {
+        pending_irq.raise(0);
     }
 }
 
@@ -61,7 +77,7 @@ pub struct Pic {
     pub primary_read_sel: ReadSel,
     pub secondary_read_sel: ReadSel,
     pub serial: Serial,
-    pub pending_irq: u16,
+    pub pending_irq: Arc<PendingIrqs>,
 }
 
 impl Pic {
@@ -80,7 +96,7 @@ impl Pic {
             primary_read_sel: ReadSel::Irr,
             secondary_read_sel: ReadSel::Irr,
             serial: Serial::default(),
-            pending_irq: 0,
+            pending_irq: Arc::new(PendingIrqs::default()),
         }
     }
 
@@ -97,7 +113,7 @@ impl Pic {
     }
 
     pub fn inject_external_interrupt(&mut self) -> Result<bool, &'static str> {
-        let pending = self.pending_irq;
+        let pending = self.pending_irq.get();
 
         if pending == 0 {
             return Ok(false);
@@ -159,7 +175,7 @@ impl Pic {
                 u32::from(interrupt_info) as u64,
             )?;
 
-            self.pending_irq &= !irq_bit;
+            self.pending_irq.clear(i as u8);
             return Ok(true);
         }
 
@@ -246,7 +262,7 @@ impl Pic {
             0x3F9 => {
                 self.serial.ier = regs.rax as u8;
                 if regs.rax & 0b10 != 0 {
-                    self.pending_irq |= 1 << 4;
+                    self.pending_irq.raise(4);
                 }
             }
             0x3FA => {}
@@ -7,12 +7,15 @@ use raw_cpuid::cpuid;
 use x86::controlregs::cr4;
 use x86_64::{
     registers::control::{Cr4, Cr4Flags},
-    structures::paging::{FrameAllocator, Size4KiB},
-    VirtAddr,
+    structures::paging::{FrameAllocator, PageSize, Size2MiB, Size4KiB},
 };
 
 use crate::{
-    info, interrupt,
+    constant::DEFAULT_GUEST_MEMORY_SIZE,
+    crash, info,
+    interrupt::{self, idt::IRQ_TIMER, subscriber::Subscription},
+    memory::{stack::GuardedStack, LargeFrameAllocator},
+    trace,
     vmm::{
         x86_64::{
             common::{self, read_msr, X86VCpu},
@@ -36,7 +39,12 @@ use crate::{
     },
 };
 const TEMP_STACK_SIZE: usize = 4096;
-static mut TEMP_STACK: [u8; TEMP_STACK_SIZE + 0x10] = [0; TEMP_STACK_SIZE + 0x10];
+static mut TEMP_STACK: GuardedStack<TEMP_STACK_SIZE> = GuardedStack::new();
+
+/// Guard page below the host stack used on VM exits.
+pub fn vmexit_stack_guard() -> u64 {
+    GuardedStack::guard_page(&raw const TEMP_STACK)
+}
 
 #[repr(C)]
 pub struct IntelVCpu {
@@ -55,6 +63,8 @@ pub struct IntelVCpu {
     io_bitmap: IOBitmap,
     pub host_xcr0: u64,
     pub guest_xcr0: XCR0,
+    /// Feeds host interrupts into `pic`; dropped with the vCPU.
+    interrupt_subscription: Option<Subscription>,
 }
 
 impl IntelVCpu {
@@ -66,6 +76,15 @@ impl IntelVCpu {
     fn vmexit_handler(&mut self) -> Result<(), &'static str> {
         use x86::vmx::vmcs;
         let exit_reason_raw = vmread(vmcs::ro::EXIT_REASON)? as u32;
+        let qualification = vmread(vmcs::ro::EXIT_QUALIFICATION)?;
+        let guest_rip = vmread(vmcs::guest::RIP)?;
+        crash::record_vm_exit(exit_reason_raw, qualification, guest_rip);
+        trace!(
+            "VM exit: reason {:#x}, qualification {:#x}, RIP {:#x}",
+            exit_reason_raw,
+            qualification,
+            guest_rip
+        );
 
         if exit_reason_raw & (1 << 31) != 0 {
             let reason = exit_reason_raw & 0xFF;
@@ -326,7 +345,7 @@ impl IntelVCpu {
 
     fn activate(
         &mut self,
-        frame_allocator: &mut dyn FrameAllocator<Size4KiB>,
+        frame_allocator: &mut dyn LargeFrameAllocator,
     ) -> Result<(), &'static str> {
         let revision_id = common::read_msr(0x480) as u32;
         self.vmcs.write_revision_id(revision_id);
@@ -338,10 +357,11 @@ impl IntelVCpu {
         self.setup_guest_state()?;
         self.io_bitmap.setup()?;
 
-        interrupt::subscriber::subscribe(
-            vmm_interrupt_subscriber,
-            self as &mut IntelVCpu as *mut IntelVCpu as *mut core::ffi::c_void,
-        )?;
+        let pending_irq = self.pic.pending_irq.clone();
+        self.interrupt_subscription = Some(interrupt::subscriber::subscribe(
+            IRQ_TIMER as u8,
+            move |context| vmm_interrupt_subscriber(&pending_irq, context),
+        )?);
 
         self.init_guest_memory(frame_allocator)?;
 
@@ -359,18 +379,37 @@ impl IntelVCpu {
 
     fn init_guest_memory(
         &mut self,
-        frame_allocator: &mut dyn FrameAllocator<Size4KiB>,
+        frame_allocator: &mut dyn LargeFrameAllocator,
     ) -> Result<(), &'static str> {
-        let mut pages = self.guest_memory_size / 0x1000;
         let mut gpa = 0;
+        let mut use_large_pages = true;
+
+        while gpa < self.guest_memory_size {
+            // Back guest RAM with 2MiB pages until contiguous host memory runs out.
+            let large_frame = if use_large_pages
+                && gpa % Size2MiB::SIZE == 0
+                && self.guest_memory_size - gpa >= Size2MiB::SIZE
+            {
+                FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator)
+            } else {
+                None
+            };
 
-        while pages > 0 {
-            let frame = frame_allocator.allocate_frame().ok_or("No free frames")?;
-            let hpa = frame.start_address().as_u64();
-
-            self.ept.map_4k(gpa, hpa, frame_allocator)?;
-            gpa += 0x1000;
-            pages -= 1;
+            match large_frame {
+                Some(frame) => {
+                    let hpa = frame.start_address().as_u64();
+                    self.ept.map_2m(gpa, hpa, frame_allocator)?;
+                    gpa += Size2MiB::SIZE;
+                }
+                None => {
+                    use_large_pages = false;
+                    let frame = FrameAllocator::<Size4KiB>::allocate_frame(frame_allocator)
+                        .ok_or("No free frames")?;
+                    let hpa = frame.start_address().as_u64();
+                    self.ept.map_4k(gpa, hpa, frame_allocator)?;
+                    gpa += Size4KiB::SIZE;
+                }
+            }
         }
 
         let eptp = ept::Eptp::init(&self.ept.root_table);
@@ -394,10 +433,7 @@ impl IntelVCpu {
             vmcs::host::RIP,
             crate::vmm::x86_64::intel::asm::asm_vmexit_handler as usize as u64,
         )?;
-        vmwrite(
-            vmcs::host::RSP,
-            VirtAddr::from_ptr(&raw mut TEMP_STACK).as_u64() + TEMP_STACK_SIZE as u64,
-        )?;
+        vmwrite(vmcs::host::RSP, GuardedStack::top(&raw const TEMP_STACK))?;
 
         vmwrite(vmcs::host::ES_SELECTOR, es().bits() as u64)?;
         vmwrite(vmcs::host::CS_SELECTOR, cs().bits() as u64)?;
@@ -835,10 +871,7 @@ impl IntelVCpu {
 }
 
 impl VCpu for IntelVCpu {
-    fn run(
-        &mut self,
-        frame_allocator: &mut dyn FrameAllocator<Size4KiB>,
-    ) -> Result<(), &'static str> {
+    fn run(&mut self, frame_allocator: &mut dyn LargeFrameAllocator) -> Result<(), &'static str> {
         if !self.activated {
             self.activate(frame_allocator)?;
             self.dump_vmcs_settings()?;
@@ -905,7 +938,9 @@ impl VCpu for IntelVCpu {
             vmcs,
             ept,
             eptp,
-            guest_memory_size: 1024 * 1024 * 256, // 256 MiB
+            guest_memory_size: *crate::GUEST_MEMORY_SIZE
+                .get()
+                .unwrap_or(&DEFAULT_GUEST_MEMORY_SIZE),
             host_msr: ShadowMsr::new(),
             guest_msr: ShadowMsr::new(),
             ia32e_enabled: false,
@@ -913,6 +948,7 @@ impl VCpu for IntelVCpu {
             io_bitmap: IOBitmap::new(frame_allocator),
             host_xcr0: 0,
             guest_xcr0: XCR0::new(),
+            interrupt_subscription: None,
         })
     }
 